@@ -1,5 +1,6 @@
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use tauri::{path::BaseDirectory, AppHandle, Manager};
 
 use crate::APP_HANDLE;
@@ -48,6 +49,395 @@ pub struct Config {
     pub always_show_icons: Option<bool>,
     pub hide_the_icon_in_the_dock: Option<bool>,
     pub configured: Option<bool>,
+    pub recording_profile: Option<RecordingProfile>,
+    pub monitor_id: Option<u32>,
+    pub monitor_name: Option<String>,
+    pub monitor_follow_cursor: Option<bool>,
+    pub capture_mode: Option<CaptureMode>,
+    /// Capture rect in monitor coordinates, used by `CaptureMode::Region`.
+    pub capture_region: Option<Rect>,
+    /// Title (or app name) of the window recorded by `CaptureMode::Window`.
+    pub capture_window: Option<String>,
+    /// Draw the mouse pointer into recordings, on unless set to false.
+    pub show_cursor: Option<bool>,
+    /// V4L2 device recorded by `CaptureMode::Camera`, the first camera when
+    /// unset. `fake` streams a test pattern in debug builds.
+    pub camera_device: Option<String>,
+    /// Picture-in-picture webcam on screen recordings, from `camera_device`.
+    pub webcam_overlay: Option<WebcamOverlay>,
+    /// Click ripples and key combo captions drawn over recordings.
+    pub input_overlay: Option<InputOverlay>,
+    /// Text, timestamp and logo burned into recordings.
+    pub watermark: Option<Watermark>,
+    /// Screen areas hidden from recordings, changed mid-recording through
+    /// `set_privacy_masks`.
+    pub privacy_masks: Option<Vec<PrivacyMask>>,
+    /// Name of the cpal input device, the default device when unset or gone.
+    pub audio_input: Option<String>,
+    /// Mix the output monitor/loopback source into the recording.
+    pub system_audio: Option<bool>,
+    /// Loopback input device name, detected by name when unset.
+    pub system_audio_device: Option<String>,
+    pub mic_gain: Option<f32>,
+    pub system_audio_gain: Option<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum CaptureMode {
+    Monitor,
+    Region,
+    Window,
+    /// A webcam, V4L2 only.
+    Camera,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Vp8,
+    Vp9,
+}
+
+/// Encoding parameters for a recording session, tunable per machine.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingProfile {
+    pub codec: Option<Codec>,
+    /// Video bitrate in kbit/s.
+    pub video_bitrate: Option<u32>,
+    pub fps: Option<u32>,
+    /// Opus bitrate in bit/s.
+    pub audio_bitrate: Option<i32>,
+    pub output_dir: Option<String>,
+    pub overload_policy: Option<OverloadPolicy>,
+    /// Cap on raw video waiting in the pipeline, in MiB.
+    pub max_queue_memory: Option<u32>,
+    pub color_range: Option<ColorRange>,
+    /// Encoded size, the captured area is scaled to fit it keeping its aspect.
+    /// Either one alone derives the other; unset records at capture size.
+    pub output_width: Option<u32>,
+    pub output_height: Option<u32>,
+    /// Pad to exactly the output size with black bars instead of encoding
+    /// just the fitted picture.
+    pub letterbox: Option<bool>,
+    pub scale_filter: Option<ScaleFilter>,
+    /// How long a missing frame may hold back the ones after it before it is
+    /// skipped, in milliseconds.
+    pub reorder_timeout: Option<u32>,
+    /// Reuse converted frame buffers instead of allocating one per frame.
+    pub pool_buffers: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleFilter {
+    /// Fast, softens small text when shrinking a lot.
+    Bilinear,
+    /// Sharper, keeps text legible at about three times the cost.
+    Lanczos,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorRange {
+    /// 16-235 luma, what most players assume for video.
+    Limited,
+    /// 0-255 luma.
+    Full,
+}
+
+/// Where and how the webcam is drawn over the screen.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct WebcamOverlay {
+    pub enabled: Option<bool>,
+    pub corner: Option<Corner>,
+    /// Gap to the edges of the recording, in output pixels.
+    pub margin: Option<u32>,
+    /// Width as a fraction of the recording's width.
+    pub size: Option<f32>,
+    pub shape: Option<OverlayShape>,
+    /// Corner radius of `OverlayShape::Rounded`, in output pixels.
+    pub corner_radius: Option<u32>,
+    /// Flip horizontally, the way people see themselves in a preview.
+    pub mirror: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Shows what the user does with the mouse and keyboard, for how-to videos.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct InputOverlay {
+    pub enabled: Option<bool>,
+    /// Draw a ripple where a mouse button goes down.
+    pub clicks: Option<bool>,
+    /// Caption pressed key combos.
+    pub keys: Option<bool>,
+    /// How long a click ripple grows and fades, in milliseconds.
+    pub click_duration: Option<u32>,
+    /// How long a caption stays after the last key, in milliseconds.
+    pub caption_duration: Option<u32>,
+    /// Fade out at the end of `caption_duration`, in milliseconds.
+    pub caption_fade: Option<u32>,
+    pub caption_position: Option<OverlayPosition>,
+    /// Gap between the caption and the edges, in output pixels.
+    pub margin: Option<u32>,
+    /// Combos to caption, written like `Ctrl+Shift+P` or `Enter`. When unset,
+    /// only keys that type no text, like `Enter` or `F5`, are shown, with the
+    /// modifiers held. Shortcuts have to be listed: modifiers are tracked
+    /// from global events, and one stuck after a missed release would turn
+    /// typed text, passwords included, into shortcuts.
+    pub key_allowlist: Option<Vec<String>>,
+}
+
+/// Text and a logo burned into every frame, for recordings that have to show
+/// who made them and when.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Watermark {
+    pub enabled: Option<bool>,
+    /// A `chrono` strftime pattern formatted with the wall-clock time of each
+    /// frame, `{user}` is replaced by the login name. Lines are split on `\n`.
+    pub text: Option<String>,
+    /// TrueType or OpenType font file, the built-in bitmap font when unset.
+    pub font: Option<String>,
+    /// Line height in output pixels.
+    pub font_size: Option<u32>,
+    pub text_position: Option<OverlayPosition>,
+    /// PNG logo.
+    pub image: Option<String>,
+    /// Logo width in output pixels, its own size when unset.
+    pub image_width: Option<u32>,
+    pub image_position: Option<OverlayPosition>,
+    /// 0 for invisible to 1 for opaque.
+    pub opacity: Option<f32>,
+    /// Gap to the edges of the recording, in output pixels.
+    pub margin: Option<u32>,
+}
+
+/// An area kept out of recordings, fixed or following a window.
+#[derive(Debug, Serialize, Deserialize, Clone, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct PrivacyMask {
+    /// Fixed area in the coordinates of the recorded monitor, or of the
+    /// recorded window.
+    pub region: Option<Rect>,
+    /// Title (or app name) of a window hidden wherever it moves. Used instead
+    /// of `region` when set.
+    pub window: Option<String>,
+    /// How the area is hidden, filled black when unset.
+    pub style: Option<MaskStyle>,
+    /// Block size for `pixelate` or radius for `blur`, in captured pixels.
+    pub strength: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub enum MaskStyle {
+    Pixelate,
+    Blur,
+    Fill,
+}
+
+/// Where an overlay sits in the recording.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum OverlayPosition {
+    TopLeft,
+    Top,
+    TopRight,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlayShape {
+    /// The camera's own aspect ratio with rounded corners.
+    Rounded,
+    /// A circle cut from the middle of the picture.
+    Circle,
+}
+
+/// What the producer does when the encoder can't keep up.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum OverloadPolicy {
+    /// Drop the video of the oldest queued frame.
+    DropOldest,
+    /// Drop the video of the frame just captured.
+    DropNewest,
+    /// Wait for room, which stalls capture.
+    Block,
+}
+
+impl RecordingProfile {
+    pub fn codec(&self) -> Codec {
+        self.codec.unwrap_or(Codec::Vp9)
+    }
+
+    pub fn video_bitrate(&self) -> u32 {
+        self.video_bitrate.unwrap_or(5000)
+    }
+
+    pub fn fps(&self) -> u32 {
+        self.fps.filter(|fps| *fps > 0).unwrap_or(60)
+    }
+
+    pub fn audio_bitrate(&self) -> i32 {
+        self.audio_bitrate.unwrap_or(128000)
+    }
+
+    pub fn output_dir(&self) -> String {
+        self.output_dir.clone().unwrap_or("target".to_string())
+    }
+
+    pub fn overload_policy(&self) -> OverloadPolicy {
+        self.overload_policy.unwrap_or(OverloadPolicy::DropOldest)
+    }
+
+    pub fn max_queue_memory(&self) -> usize {
+        self.max_queue_memory.unwrap_or(512) as usize * 1024 * 1024
+    }
+
+    pub fn color_range(&self) -> ColorRange {
+        self.color_range.unwrap_or(ColorRange::Limited)
+    }
+
+    pub fn letterbox(&self) -> bool {
+        self.letterbox.unwrap_or(false)
+    }
+
+    pub fn scale_filter(&self) -> ScaleFilter {
+        self.scale_filter.unwrap_or(ScaleFilter::Lanczos)
+    }
+
+    pub fn reorder_timeout(&self) -> Duration {
+        Duration::from_millis(self.reorder_timeout.unwrap_or(1000) as u64)
+    }
+
+    pub fn pool_buffers(&self) -> bool {
+        self.pool_buffers.unwrap_or(true)
+    }
+}
+
+impl WebcamOverlay {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn corner(&self) -> Corner {
+        self.corner.unwrap_or(Corner::BottomRight)
+    }
+
+    pub fn margin(&self) -> u32 {
+        self.margin.unwrap_or(24)
+    }
+
+    pub fn size(&self) -> f32 {
+        self.size.unwrap_or(0.25).clamp(0.05, 1.0)
+    }
+
+    pub fn shape(&self) -> OverlayShape {
+        self.shape.unwrap_or(OverlayShape::Rounded)
+    }
+
+    pub fn corner_radius(&self) -> u32 {
+        self.corner_radius.unwrap_or(16)
+    }
+
+    pub fn mirror(&self) -> bool {
+        self.mirror.unwrap_or(false)
+    }
+}
+
+impl InputOverlay {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn clicks(&self) -> bool {
+        self.clicks.unwrap_or(true)
+    }
+
+    pub fn keys(&self) -> bool {
+        self.keys.unwrap_or(true)
+    }
+
+    pub fn click_duration(&self) -> Duration {
+        Duration::from_millis(self.click_duration.unwrap_or(500).max(1) as u64)
+    }
+
+    pub fn caption_duration(&self) -> Duration {
+        Duration::from_millis(self.caption_duration.unwrap_or(1500).max(1) as u64)
+    }
+
+    pub fn caption_fade(&self) -> Duration {
+        Duration::from_millis(self.caption_fade.unwrap_or(300) as u64).min(self.caption_duration())
+    }
+
+    pub fn caption_position(&self) -> OverlayPosition {
+        self.caption_position.unwrap_or(OverlayPosition::Bottom)
+    }
+
+    pub fn margin(&self) -> u32 {
+        self.margin.unwrap_or(48)
+    }
+}
+
+impl Watermark {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn font_size(&self) -> u32 {
+        self.font_size.unwrap_or(24).max(8)
+    }
+
+    pub fn text_position(&self) -> OverlayPosition {
+        self.text_position.unwrap_or(OverlayPosition::BottomLeft)
+    }
+
+    pub fn image_position(&self) -> OverlayPosition {
+        self.image_position.unwrap_or(OverlayPosition::TopRight)
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity.unwrap_or(0.8).clamp(0.0, 1.0)
+    }
+
+    pub fn margin(&self) -> u32 {
+        self.margin.unwrap_or(24)
+    }
+}
+
+impl PrivacyMask {
+    pub fn style(&self) -> MaskStyle {
+        self.style.unwrap_or(MaskStyle::Fill)
+    }
+
+    pub fn strength(&self) -> u32 {
+        self.strength.unwrap_or(16).clamp(2, 256)
+    }
 }
 
 pub fn get_config() -> Result<Config, Box<dyn std::error::Error>> {
@@ -111,6 +501,105 @@ pub fn merge_config(cfg: Config, old: Config) -> Config {
         hide_the_icon_in_the_dock: cfg
             .hide_the_icon_in_the_dock
             .or(old.hide_the_icon_in_the_dock),
+        recording_profile: merge_recording_profile(cfg.recording_profile, old.recording_profile),
+        monitor_id: cfg.monitor_id.or(old.monitor_id),
+        monitor_name: cfg.monitor_name.or(old.monitor_name),
+        monitor_follow_cursor: cfg.monitor_follow_cursor.or(old.monitor_follow_cursor),
+        capture_mode: cfg.capture_mode.or(old.capture_mode),
+        capture_region: cfg.capture_region.or(old.capture_region),
+        capture_window: cfg.capture_window.or(old.capture_window),
+        show_cursor: cfg.show_cursor.or(old.show_cursor),
+        camera_device: cfg.camera_device.or(old.camera_device),
+        webcam_overlay: merge_webcam_overlay(cfg.webcam_overlay, old.webcam_overlay),
+        input_overlay: merge_input_overlay(cfg.input_overlay, old.input_overlay),
+        watermark: merge_watermark(cfg.watermark, old.watermark),
+        privacy_masks: cfg.privacy_masks.or(old.privacy_masks),
+        audio_input: cfg.audio_input.or(old.audio_input),
+        system_audio: cfg.system_audio.or(old.system_audio),
+        system_audio_device: cfg.system_audio_device.or(old.system_audio_device),
+        mic_gain: cfg.mic_gain.or(old.mic_gain),
+        system_audio_gain: cfg.system_audio_gain.or(old.system_audio_gain),
+    }
+}
+
+fn merge_recording_profile(
+    cfg: Option<RecordingProfile>,
+    old: Option<RecordingProfile>,
+) -> Option<RecordingProfile> {
+    match (cfg, old) {
+        (Some(cfg), Some(old)) => Some(RecordingProfile {
+            codec: cfg.codec.or(old.codec),
+            video_bitrate: cfg.video_bitrate.or(old.video_bitrate),
+            fps: cfg.fps.or(old.fps),
+            audio_bitrate: cfg.audio_bitrate.or(old.audio_bitrate),
+            output_dir: cfg.output_dir.or(old.output_dir),
+            overload_policy: cfg.overload_policy.or(old.overload_policy),
+            max_queue_memory: cfg.max_queue_memory.or(old.max_queue_memory),
+            color_range: cfg.color_range.or(old.color_range),
+            output_width: cfg.output_width.or(old.output_width),
+            output_height: cfg.output_height.or(old.output_height),
+            letterbox: cfg.letterbox.or(old.letterbox),
+            scale_filter: cfg.scale_filter.or(old.scale_filter),
+            reorder_timeout: cfg.reorder_timeout.or(old.reorder_timeout),
+            pool_buffers: cfg.pool_buffers.or(old.pool_buffers),
+        }),
+        (cfg, old) => cfg.or(old),
+    }
+}
+
+fn merge_webcam_overlay(
+    cfg: Option<WebcamOverlay>,
+    old: Option<WebcamOverlay>,
+) -> Option<WebcamOverlay> {
+    match (cfg, old) {
+        (Some(cfg), Some(old)) => Some(WebcamOverlay {
+            enabled: cfg.enabled.or(old.enabled),
+            corner: cfg.corner.or(old.corner),
+            margin: cfg.margin.or(old.margin),
+            size: cfg.size.or(old.size),
+            shape: cfg.shape.or(old.shape),
+            corner_radius: cfg.corner_radius.or(old.corner_radius),
+            mirror: cfg.mirror.or(old.mirror),
+        }),
+        (cfg, old) => cfg.or(old),
+    }
+}
+
+fn merge_input_overlay(
+    cfg: Option<InputOverlay>,
+    old: Option<InputOverlay>,
+) -> Option<InputOverlay> {
+    match (cfg, old) {
+        (Some(cfg), Some(old)) => Some(InputOverlay {
+            enabled: cfg.enabled.or(old.enabled),
+            clicks: cfg.clicks.or(old.clicks),
+            keys: cfg.keys.or(old.keys),
+            click_duration: cfg.click_duration.or(old.click_duration),
+            caption_duration: cfg.caption_duration.or(old.caption_duration),
+            caption_fade: cfg.caption_fade.or(old.caption_fade),
+            caption_position: cfg.caption_position.or(old.caption_position),
+            margin: cfg.margin.or(old.margin),
+            key_allowlist: cfg.key_allowlist.or(old.key_allowlist),
+        }),
+        (cfg, old) => cfg.or(old),
+    }
+}
+
+fn merge_watermark(cfg: Option<Watermark>, old: Option<Watermark>) -> Option<Watermark> {
+    match (cfg, old) {
+        (Some(cfg), Some(old)) => Some(Watermark {
+            enabled: cfg.enabled.or(old.enabled),
+            text: cfg.text.or(old.text),
+            font: cfg.font.or(old.font),
+            font_size: cfg.font_size.or(old.font_size),
+            text_position: cfg.text_position.or(old.text_position),
+            image: cfg.image.or(old.image),
+            image_width: cfg.image_width.or(old.image_width),
+            image_position: cfg.image_position.or(old.image_position),
+            opacity: cfg.opacity.or(old.opacity),
+            margin: cfg.margin.or(old.margin),
+        }),
+        (cfg, old) => cfg.or(old),
     }
 }
 
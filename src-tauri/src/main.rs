@@ -7,6 +7,10 @@ mod windows;
 mod recorder;
 
 use crate::config::{clear_config_cache, get_config_content, update_config};
+use crate::recorder::{
+    list_audio_inputs, list_cameras, list_monitors, list_windows, set_privacy_masks,
+    RecordingFailed, RecordingPaused, RecordingStarted, RecordingStopped,
+};
 use crate::windows::get_window_always_on_top;
 
 use parking_lot::Mutex;
@@ -52,10 +56,19 @@ fn main() {
             clear_config_cache,
             get_window_always_on_top,
             update_config,
+            list_monitors,
+            list_windows,
+            list_audio_inputs,
+            list_cameras,
+            set_privacy_masks,
         ])
         .events(tauri_specta::collect_events![
             PinnedFromWindowEvent,
             PinnedFromTrayEvent,
+            RecordingStarted,
+            RecordingPaused,
+            RecordingStopped,
+            RecordingFailed,
         ]);
 
     #[cfg(debug_assertions)] // <- Only export on non-release builds
@@ -0,0 +1,93 @@
+/// Buffers converted samples across packets and cuts them into exact Opus frames.
+///
+/// Packets carry however many samples the device delivered since the last
+/// video frame, which rarely matches a frame size Opus accepts. Frames are
+/// stamped from the running sample count, so they line up back to back.
+pub struct FrameAccumulator {
+    sample_rate: u64,
+    channels: usize,
+    frame_len: usize,
+    buffer: Vec<f32>,
+    /// Per-channel frames emitted so far.
+    emitted: u64,
+}
+
+impl FrameAccumulator {
+    /// 20 ms is the frame duration Opus is tuned for.
+    pub const FRAME_MS: u32 = 20;
+
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let frame_len = (sample_rate * Self::FRAME_MS / 1000) as usize * channels;
+        Self {
+            sample_rate: sample_rate as u64,
+            channels,
+            frame_len,
+            buffer: Vec::with_capacity(frame_len * 2),
+            emitted: 0,
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        self.buffer.extend_from_slice(samples);
+    }
+
+    /// Next complete frame and its timestamp in nanoseconds.
+    pub fn pop(&mut self) -> Option<(u64, Vec<f32>)> {
+        if self.buffer.len() < self.frame_len {
+            return None;
+        }
+        let frame: Vec<f32> = self.buffer.drain(..self.frame_len).collect();
+        Some(self.stamp(frame))
+    }
+
+    /// The leftover tail padded with silence to a full frame, used at stop.
+    pub fn flush(&mut self) -> Option<(u64, Vec<f32>)> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let mut frame = std::mem::take(&mut self.buffer);
+        frame.resize(self.frame_len, 0.0);
+        Some(self.stamp(frame))
+    }
+
+    fn stamp(&mut self, frame: Vec<f32>) -> (u64, Vec<f32>) {
+        let timestamp = self.emitted * 1_000_000_000 / self.sample_rate;
+        self.emitted += (frame.len() / self.channels) as u64;
+        (timestamp, frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_are_cut_into_back_to_back_frames() {
+        // 20 ms of stereo at 48 kHz is 960 frames of two samples.
+        let mut accumulator = FrameAccumulator::new(48000, 2);
+        accumulator.push(&[0.5; 1000]);
+        assert!(accumulator.pop().is_none());
+
+        accumulator.push(&[0.5; 3000]);
+        let (first, frame) = accumulator.pop().unwrap();
+        assert_eq!((first, frame.len()), (0, 1920));
+        let (second, frame) = accumulator.pop().unwrap();
+        assert_eq!((second, frame.len()), (20_000_000, 1920));
+        assert!(accumulator.pop().is_none());
+    }
+
+    #[test]
+    fn the_tail_is_padded_with_silence() {
+        let mut accumulator = FrameAccumulator::new(48000, 2);
+        accumulator.push(&[0.5; 2000]);
+        accumulator.pop().unwrap();
+
+        let (timestamp, frame) = accumulator.flush().unwrap();
+        assert_eq!(timestamp, 20_000_000);
+        assert_eq!(frame.len(), 1920);
+        assert!(frame[..80].iter().all(|s| *s == 0.5));
+        assert!(frame[80..].iter().all(|s| *s == 0.0));
+        assert!(accumulator.flush().is_none());
+    }
+}
@@ -1,29 +1,510 @@
-use opencv::{highgui, prelude::*, videoio, Result};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-fn video_capture() -> Result<()> {
-    // Open the default camera (camera index 0)
-    let mut camera = videoio::VideoCapture::new(0, videoio::CAP_ANY)?;
+use anyhow::{anyhow, bail, Context};
+use v4l::buffer::Type;
+use v4l::framesize::FrameSizeEnum;
+use v4l::io::mmap::Stream as MmapStream;
+use v4l::io::traits::CaptureStream;
+use v4l::video::Capture;
+use v4l::{Device, Format, FourCC};
 
-    if !videoio::VideoCapture::is_opened(&camera)? {
-        panic!("Could not open the camera.");
+use super::source::{CameraFormat, CameraInfo, Frame};
+use super::ResultType;
+
+/// Device path of a synthetic camera that streams a moving test pattern, for
+/// trying the camera pipeline without hardware. Debug builds only; a
+/// v4l2loopback device works as a real one anywhere.
+pub const FAKE_CAMERA: &str = "fake";
+
+const YUYV: FourCC = FourCC { repr: *b"YUYV" };
+const MJPG: FourCC = FourCC { repr: *b"MJPG" };
+
+// Largest size picked, bigger ones rarely stream at a usable frame rate.
+const MAX_WIDTH: u32 = 1920;
+const MAX_HEIGHT: u32 = 1080;
+
+const FAKE_WIDTH: u32 = 640;
+const FAKE_HEIGHT: u32 = 480;
+const FAKE_INTERVAL: Duration = Duration::from_millis(33);
+
+// How long the first frame may take while the device starts streaming.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+// Frames kept for lining the camera up with another source's timestamps.
+const RECENT_FRAMES: usize = 8;
+
+/// Lists V4L2 capture devices that deliver YUYV or MJPEG.
+pub fn list() -> Vec<CameraInfo> {
+    let mut cameras: Vec<CameraInfo> = v4l::context::enum_devices()
+        .iter()
+        .filter_map(|node| {
+            let device = Device::with_path(node.path()).ok()?;
+            let formats = formats(&device);
+            if formats.is_empty() {
+                // Metadata nodes and output-only devices.
+                return None;
+            }
+            Some(CameraInfo {
+                path: node.path().display().to_string(),
+                name: node
+                    .name()
+                    .unwrap_or_else(|| node.path().display().to_string()),
+                formats,
+            })
+        })
+        .collect();
+    cameras.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if cfg!(debug_assertions) {
+        cameras.push(CameraInfo {
+            path: FAKE_CAMERA.to_string(),
+            name: "Test pattern".to_string(),
+            formats: vec![CameraFormat {
+                fourcc: "YUYV".to_string(),
+                width: FAKE_WIDTH,
+                height: FAKE_HEIGHT,
+            }],
+        });
+    }
+    cameras
+}
+
+fn formats(device: &Device) -> Vec<CameraFormat> {
+    let mut formats = Vec::new();
+    for description in device.enum_formats().unwrap_or_default() {
+        let fourcc = description.fourcc;
+        if fourcc != YUYV && fourcc != MJPG {
+            continue;
+        }
+        for size in device.enum_framesizes(fourcc).unwrap_or_default() {
+            let (width, height) = match size.size {
+                FrameSizeEnum::Discrete(size) => (size.width, size.height),
+                // Only the largest, every step would be thousands of sizes.
+                FrameSizeEnum::Stepwise(size) => (size.max_width, size.max_height),
+            };
+            formats.push(CameraFormat {
+                fourcc: fourcc.str().unwrap_or_default().to_string(),
+                width,
+                height,
+            });
+        }
     }
+    formats
+}
+
+/// A webcam streaming on its own thread.
+///
+/// The camera runs at its own rate, so frames are dequeued and decoded to
+/// ARGB as they arrive and `capture` hands out the latest one stamped with
+/// when it was dequeued. When the producer asks faster than the camera
+/// delivers, the last frame is repeated like a window that didn't change.
+pub struct Camera {
+    name: String,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+    /// Sequence number of the frame `capture` returned last.
+    last: u64,
+}
 
-    let window_name = "Camera Capture";
-    highgui::named_window(window_name, highgui::WINDOW_AUTOSIZE)?;
+#[derive(Default)]
+struct Shared {
+    latest: Mutex<Latest>,
+    ready: Condvar,
+    stop: AtomicBool,
+}
 
-    loop {
-        let mut frame = Mat::default();
-        camera.read(&mut frame)?;
+#[derive(Default)]
+struct Latest {
+    /// The last few frames, oldest first.
+    recent: VecDeque<Arc<Frame>>,
+    seq: u64,
+    /// Why the stream stopped, once it has.
+    error: Option<String>,
+}
 
-        if frame.size()?.width > 0 {
-            highgui::imshow(window_name, &frame)?;
+impl Shared {
+    fn publish(&self, frame: Frame) {
+        let mut latest = self.latest.lock().unwrap();
+        if latest.recent.len() == RECENT_FRAMES {
+            latest.recent.pop_front();
         }
+        latest.recent.push_back(Arc::new(frame));
+        latest.seq += 1;
+        self.ready.notify_all();
+    }
 
-        // Exit on ESC key
-        if highgui::wait_key(10)? == 27 {
-            break;
+    fn fail(&self, error: String) {
+        self.latest.lock().unwrap().error = Some(error);
+        self.ready.notify_all();
+    }
+}
+
+impl Camera {
+    /// Opens `path`, or the first camera found when `None`.
+    pub fn open(path: Option<&str>) -> ResultType<Self> {
+        let path = match path {
+            Some(path) => path.to_string(),
+            None => list()
+                .into_iter()
+                .map(|camera| camera.path)
+                .next()
+                .ok_or_else(|| anyhow!("no camera found"))?,
+        };
+        let shared = Arc::new(Shared::default());
+
+        if cfg!(debug_assertions) && path == FAKE_CAMERA {
+            let stream_shared = shared.clone();
+            let thread = std::thread::spawn(move || fake_stream(&stream_shared));
+            return Ok(Self {
+                name: "Test pattern".to_string(),
+                shared,
+                thread: Some(thread),
+                last: 0,
+            });
         }
+
+        let device = Device::with_path(&path).with_context(|| format!("Failed to open {path}"))?;
+        let name = device
+            .query_caps()
+            .map(|caps| caps.card)
+            .unwrap_or_else(|_| path.clone());
+        let format = choose_format(&device)?;
+        let format = device
+            .set_format(&format)
+            .with_context(|| format!("Failed to set the format of {path}"))?;
+        if format.fourcc != YUYV && format.fourcc != MJPG {
+            bail!("{path} streams {} instead of YUYV or MJPEG", format.fourcc);
+        }
+        println!(
+            "camera {} at {}x{} {}",
+            name, format.width, format.height, format.fourcc
+        );
+
+        let stream = MmapStream::with_buffers(&device, Type::VideoCapture, 4)
+            .with_context(|| format!("Failed to start streaming from {path}"))?;
+        let stream_shared = shared.clone();
+        let thread = std::thread::spawn(move || {
+            // The device has to outlive its stream.
+            let _device = device;
+            if let Err(e) = device_stream(stream, format, &stream_shared) {
+                stream_shared.fail(format!("{:#}", e));
+            }
+        });
+        Ok(Self {
+            name,
+            shared,
+            thread: Some(thread),
+            last: 0,
+        })
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The latest frame, waiting for the first one to arrive.
+    pub fn capture(&mut self) -> ResultType<Frame> {
+        let latest = self.first_frame()?;
+        let frame = latest.recent.back().unwrap();
+        let fresh = latest.seq != self.last;
+        let seq = latest.seq;
+        let frame = Frame {
+            data: frame.data.clone(),
+            width: frame.width,
+            height: frame.height,
+            // A repeated frame is stamped when it is captured again.
+            timestamp: if fresh { frame.timestamp } else { None },
+        };
+        drop(latest);
+        self.last = seq;
+        Ok(frame)
+    }
+
+    /// The newest frame taken no later than `at`, so the camera lines up with
+    /// a frame captured elsewhere at that time. The oldest frame kept stands
+    /// in when all of them are newer.
+    pub fn frame_at(&self, at: Instant) -> ResultType<Arc<Frame>> {
+        let latest = self.first_frame()?;
+        let frame = latest
+            .recent
+            .iter()
+            .rev()
+            .find(|frame| frame.timestamp.is_some_and(|t| t <= at))
+            .or(latest.recent.front())
+            .unwrap();
+        Ok(frame.clone())
+    }
+
+    /// Waits for the stream to deliver its first frame, failing once it stopped.
+    fn first_frame(&self) -> ResultType<MutexGuard<'_, Latest>> {
+        let deadline = Instant::now() + FIRST_FRAME_TIMEOUT;
+        let mut latest = self.shared.latest.lock().unwrap();
+        loop {
+            if let Some(error) = &latest.error {
+                bail!("camera {} stopped: {}", self.name, error);
+            }
+            if !latest.recent.is_empty() {
+                return Ok(latest);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                bail!("camera {} sent no frame", self.name);
+            }
+            latest = self
+                .shared
+                .ready
+                .wait_timeout(latest, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+impl Drop for Camera {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The largest YUYV or MJPEG size within the limit, YUYV on a tie since it
+/// needs no decoding.
+fn choose_format(device: &Device) -> ResultType<Format> {
+    let mut best: Option<(u64, bool, CameraFormat)> = None;
+    for format in formats(device) {
+        if format.width > MAX_WIDTH || format.height > MAX_HEIGHT {
+            continue;
+        }
+        let area = format.width as u64 * format.height as u64;
+        let yuyv = format.fourcc == "YUYV";
+        if best
+            .as_ref()
+            .is_none_or(|(a, y, _)| (area, yuyv) > (*a, *y))
+        {
+            best = Some((area, yuyv, format));
+        }
+    }
+    let (_, _, format) = best.ok_or_else(|| anyhow!("no YUYV or MJPEG format"))?;
+    let fourcc = FourCC::new(format.fourcc.as_bytes().try_into()?);
+    Ok(Format::new(format.width, format.height, fourcc))
+}
+
+fn device_stream(mut stream: MmapStream, format: Format, shared: &Shared) -> ResultType<()> {
+    // Wakes up now and then to notice `stop` on a stalled device.
+    stream.set_timeout(Duration::from_millis(500));
+    let width = format.width as usize;
+    let height = format.height as usize;
+    while !shared.stop.load(Ordering::Acquire) {
+        let (buffer, meta) = match stream.next() {
+            Ok(next) => next,
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let timestamp = Instant::now();
+        let data = &buffer[..(meta.bytesused as usize).min(buffer.len())];
+
+        let frame = if format.fourcc == YUYV {
+            let mut argb = Vec::new();
+            yuyv_to_argb(data, width, height, format.stride as usize, &mut argb);
+            Frame {
+                data: argb,
+                width: format.width,
+                height: format.height,
+                timestamp: Some(timestamp),
+            }
+        } else {
+            match mjpeg_to_argb(data) {
+                Ok((argb, width, height)) => Frame {
+                    data: argb,
+                    width,
+                    height,
+                    timestamp: Some(timestamp),
+                },
+                Err(e) => {
+                    // Cameras send a broken frame now and then, the next one is fine.
+                    debug_print::debug_println!("skip camera frame {}: {}", meta.sequence, e);
+                    continue;
+                }
+            }
+        };
+        shared.publish(frame);
+    }
     Ok(())
 }
+
+/// Streams colour bars with a block moving across them, encoded as YUYV and
+/// decoded like a real camera's frames.
+fn fake_stream(shared: &Shared) {
+    let width = FAKE_WIDTH as usize;
+    let height = FAKE_HEIGHT as usize;
+    let mut yuyv = vec![0; width * height * 2];
+    let mut n = 0;
+    let start = Instant::now();
+    while !shared.stop.load(Ordering::Acquire) {
+        fake_pattern(&mut yuyv, width, height, n);
+        let mut argb = Vec::new();
+        yuyv_to_argb(&yuyv, width, height, width * 2, &mut argb);
+        shared.publish(Frame {
+            data: argb,
+            width: FAKE_WIDTH,
+            height: FAKE_HEIGHT,
+            timestamp: Some(Instant::now()),
+        });
+
+        n += 1;
+        let next = start + FAKE_INTERVAL * n;
+        std::thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+}
+
+fn fake_pattern(yuyv: &mut [u8], width: usize, height: usize, n: u32) {
+    // 75% white, yellow, cyan, green, magenta, red, blue, black as (Y, U, V).
+    const BARS: [(u8, u8, u8); 8] = [
+        (180, 128, 128),
+        (162, 44, 142),
+        (131, 156, 44),
+        (112, 72, 58),
+        (84, 184, 198),
+        (65, 100, 212),
+        (35, 212, 114),
+        (16, 128, 128),
+    ];
+    let block = height / 4;
+    let block_x = (n as usize * 8) % (width - block);
+    let block_y = (height - block) / 2;
+    for (y, row) in yuyv.chunks_exact_mut(width * 2).enumerate() {
+        for (pair, pixels) in row.chunks_exact_mut(4).enumerate() {
+            let x = pair * 2;
+            let (luma, u, v) = if (block_y..block_y + block).contains(&y)
+                && (block_x..block_x + block).contains(&x)
+            {
+                (235, 128, 128)
+            } else {
+                BARS[x * BARS.len() / width]
+            };
+            pixels.copy_from_slice(&[luma, u, luma, v]);
+        }
+    }
+}
+
+/// Converts packed YUYV (BT.601, limited range) to the pipeline's ARGB.
+fn yuyv_to_argb(src: &[u8], width: usize, height: usize, stride: usize, dest: &mut Vec<u8>) {
+    let stride = stride.max(width * 2);
+    dest.clear();
+    dest.resize(width * height * 4, 0);
+    for (row, out) in src
+        .chunks(stride)
+        .zip(dest.chunks_exact_mut(width * 4))
+        .take(height)
+    {
+        let row = &row[..(width * 2).min(row.len())];
+        for (yuyv, out) in row.chunks_exact(4).zip(out.chunks_exact_mut(8)) {
+            let d = yuyv[1] as i32 - 128;
+            let e = yuyv[3] as i32 - 128;
+            for (luma, out) in [yuyv[0], yuyv[2]].into_iter().zip(out.chunks_exact_mut(4)) {
+                let c = 298 * (luma as i32 - 16) + 128;
+                out[0] = clamp((c + 516 * d) >> 8);
+                out[1] = clamp((c - 100 * d - 208 * e) >> 8);
+                out[2] = clamp((c + 409 * e) >> 8);
+                out[3] = 255;
+            }
+        }
+    }
+}
+
+fn mjpeg_to_argb(src: &[u8]) -> ResultType<(Vec<u8>, u32, u32)> {
+    let image = image::load_from_memory_with_format(src, image::ImageFormat::Jpeg)?.into_rgba8();
+    let (width, height) = image.dimensions();
+    let mut data = image.into_raw();
+    // RGBA -> BGRA, the layout `capture_bytes` hands out.
+    for pixel in data.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+    Ok((data, width, height))
+}
+
+fn clamp(x: i32) -> u8 {
+    x.clamp(0, 255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    use super::*;
+
+    /// Left edge of the moving block on the middle row, the only pure white.
+    fn block_x(frame: &Frame) -> Option<usize> {
+        let width = frame.width as usize;
+        let row = &frame.data[frame.height as usize / 2 * width * 4..][..width * 4];
+        row.chunks_exact(4).position(|p| p == [255, 255, 255, 255])
+    }
+
+    #[test]
+    #[cfg_attr(not(debug_assertions), ignore = "the fake camera is debug only")]
+    fn fake_camera_streams_a_moving_pattern() {
+        let mut camera = Camera::open(Some(FAKE_CAMERA)).unwrap();
+        let deadline = Instant::now() + FIRST_FRAME_TIMEOUT;
+        let mut blocks = Vec::new();
+        while blocks.len() < 3 && Instant::now() < deadline {
+            let frame = camera.capture().unwrap();
+            assert_eq!((frame.width, frame.height), (FAKE_WIDTH, FAKE_HEIGHT));
+            assert_eq!(frame.data.len(), (FAKE_WIDTH * FAKE_HEIGHT * 4) as usize);
+            // 75% white on the left, black on the right.
+            assert_eq!(frame.data[..4], [191, 191, 191, 255]);
+            let last = (FAKE_WIDTH as usize - 1) * 4;
+            assert_eq!(frame.data[last..last + 4], [0, 0, 0, 255]);
+            // Only new frames, a repeated one has no timestamp.
+            if frame.timestamp.is_some() {
+                blocks.push(block_x(&frame).unwrap());
+            }
+            std::thread::sleep(FAKE_INTERVAL);
+        }
+        assert_eq!(blocks.len(), 3);
+        assert!(blocks.windows(2).all(|b| b[0] != b[1]), "{blocks:?}");
+    }
+
+    #[test]
+    fn yuyv_converts_to_bgra() {
+        // Black and white, then two reds sharing their chroma, with two
+        // bytes of padding after each row.
+        let src = [
+            16, 128, 235, 128, 0, 0, //
+            81, 90, 81, 240, 0, 0,
+        ];
+        let mut argb = Vec::new();
+        yuyv_to_argb(&src, 2, 2, 6, &mut argb);
+        assert_eq!(
+            argb,
+            [
+                0, 0, 0, 255, 255, 255, 255, 255, //
+                0, 0, 255, 255, 0, 0, 255, 255,
+            ]
+        );
+    }
+
+    #[test]
+    fn mjpeg_decodes_to_bgra() {
+        let mut jpeg = Vec::new();
+        RgbImage::from_pixel(16, 8, Rgb([250, 120, 10]))
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+        let (argb, width, height) = mjpeg_to_argb(&jpeg).unwrap();
+        assert_eq!((width, height), (16, 8));
+        assert_eq!(argb.len(), 16 * 8 * 4);
+        for pixel in argb.chunks_exact(4) {
+            // JPEG is lossy, so only close.
+            for (got, want) in pixel.iter().zip([10, 120, 250, 255]) {
+                assert!(got.abs_diff(want) <= 4, "{pixel:?}");
+            }
+        }
+        assert!(mjpeg_to_argb(&jpeg[..jpeg.len() / 2]).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+/// Audio timeline driven by the number of samples written at the encode rate.
+///
+/// Audio timestamps come from this count rather than from the wall clock of
+/// the video frame, so device buffering doesn't show up as jitter. `sync`
+/// compares it to the video clock and pads or trims once they diverge; the
+/// encoded frames are stamped by `FrameAccumulator` from the same count.
+pub struct AudioClock {
+    sample_rate: u64,
+    channels: usize,
+    /// Per-channel frames written so far.
+    frames: u64,
+    threshold: u64,
+    resyncs: u32,
+}
+
+impl AudioClock {
+    pub fn new(sample_rate: u32, channels: usize, threshold: Duration) -> Self {
+        let sample_rate = sample_rate as u64;
+        Self {
+            sample_rate,
+            channels: channels.max(1),
+            frames: 0,
+            threshold: threshold.as_millis() as u64 * sample_rate / 1000,
+            resyncs: 0,
+        }
+    }
+
+    /// Moves the clock past `samples` interleaved samples.
+    pub fn advance(&mut self, samples: usize) {
+        self.frames += (samples / self.channels) as u64;
+    }
+
+    /// Lines up `samples`, which end at video time `video_ms`, with the clock.
+    ///
+    /// When the audio fell behind by more than the threshold (dropouts,
+    /// stalled device) silence is put in front; when it ran ahead the start
+    /// of the chunk is dropped.
+    pub fn sync(&mut self, samples: Vec<f32>, video_ms: u64) -> Vec<f32> {
+        let expected = video_ms * self.sample_rate / 1000;
+        let end = self.frames + (samples.len() / self.channels) as u64;
+
+        if expected > end + self.threshold {
+            let missing = (expected - end) as usize * self.channels;
+            self.resyncs += 1;
+            let mut padded = vec![0.0; missing];
+            padded.extend(samples);
+            return padded;
+        }
+
+        if end > expected + self.threshold {
+            let extra = ((end - expected) as usize * self.channels).min(samples.len());
+            self.resyncs += 1;
+            return samples[extra..].to_vec();
+        }
+
+        samples
+    }
+
+    pub fn resyncs(&self) -> u32 {
+        self.resyncs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stereo at 1 kHz, so a millisecond is one frame of two samples.
+    fn clock() -> AudioClock {
+        AudioClock::new(1000, 2, Duration::from_millis(100))
+    }
+
+    fn frames(start: usize, count: usize) -> Vec<f32> {
+        (start..start + count)
+            .flat_map(|f| [f as f32, f as f32])
+            .collect()
+    }
+
+    #[test]
+    fn small_drift_is_left_alone() {
+        let mut clock = clock();
+        clock.advance(frames(0, 500).len());
+        let samples = frames(500, 20);
+        // Ends 80 ms before and after the video.
+        assert_eq!(clock.sync(samples.clone(), 600), samples);
+        assert_eq!(clock.sync(samples.clone(), 440), samples);
+        assert_eq!(clock.resyncs(), 0);
+    }
+
+    #[test]
+    fn audio_behind_the_video_gets_silence_in_front() {
+        let mut clock = clock();
+        clock.advance(frames(0, 500).len());
+        let synced = clock.sync(frames(500, 20), 700);
+        // Ends right at the video time again.
+        assert_eq!(synced.len(), 200 * 2);
+        assert!(synced[..180 * 2].iter().all(|s| *s == 0.0));
+        assert_eq!(synced[180 * 2..], frames(500, 20));
+        assert_eq!(clock.resyncs(), 1);
+    }
+
+    #[test]
+    fn audio_ahead_of_the_video_loses_its_start() {
+        let mut clock = clock();
+        clock.advance(frames(0, 500).len());
+        let synced = clock.sync(frames(500, 300), 600);
+        assert_eq!(synced, frames(700, 100));
+        assert_eq!(clock.resyncs(), 1);
+
+        // Never more than the chunk itself.
+        assert!(clock.sync(frames(0, 10), 0).is_empty());
+        assert_eq!(clock.resyncs(), 2);
+    }
+}
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use super::convert;
+use super::scale::Scaler;
+use super::source::Frame;
+use crate::config::{Corner, OverlayShape, Rect, ScaleFilter, WebcamOverlay};
+
+/// Draws the webcam over the recorded frame, picture-in-picture.
+///
+/// The camera picture is cropped to the overlay's aspect ratio, scaled,
+/// masked to its shape and blended onto the canvas right before the I420
+/// conversion. A camera frame shown with several screen frames is only
+/// scaled once per converter.
+#[derive(Clone)]
+pub struct Compositor {
+    canvas_width: u32,
+    canvas_height: u32,
+    settings: WebcamOverlay,
+    /// Camera size the layout below was made for.
+    camera_size: (u32, u32),
+    /// Where the overlay goes on the canvas.
+    area: Rect,
+    /// Part of the camera picture that is shown.
+    crop: Rect,
+    scaler: Option<Scaler>,
+    /// Coverage of each overlay pixel, 0 to 255.
+    mask: Vec<u8>,
+    /// Camera frame in `scaled`.
+    last: Option<Arc<Frame>>,
+    cropped: Vec<u8>,
+    scaled: Vec<u8>,
+}
+
+impl Compositor {
+    pub fn new(canvas_width: u32, canvas_height: u32, settings: &WebcamOverlay) -> Self {
+        Self {
+            canvas_width,
+            canvas_height,
+            settings: settings.clone(),
+            camera_size: (0, 0),
+            area: Rect {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            },
+            crop: Rect {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            },
+            scaler: None,
+            mask: Vec::new(),
+            last: None,
+            cropped: Vec::new(),
+            scaled: Vec::new(),
+        }
+    }
+
+    /// Blends `camera` onto `canvas`, a packed ARGB frame of the canvas size.
+    pub fn compose(&mut self, camera: &Arc<Frame>, canvas: &mut [u8]) {
+        if camera.width < 2 || camera.height < 2 {
+            return;
+        }
+        if (camera.width, camera.height) != self.camera_size {
+            self.layout(camera.width, camera.height);
+        }
+        let Some(scaler) = self.scaler.as_mut() else {
+            return;
+        };
+
+        if !self
+            .last
+            .as_ref()
+            .is_some_and(|last| Arc::ptr_eq(last, camera))
+        {
+            convert::crop_argb(
+                &camera.data,
+                camera.width as usize,
+                camera.height as usize,
+                self.crop.x as usize,
+                self.crop.y as usize,
+                self.crop.width as usize,
+                self.crop.height as usize,
+                &mut self.cropped,
+            );
+            // The overlay is small, one thread scales it.
+            scaler.scale(&self.cropped, 1, &mut self.scaled);
+            self.last = Some(camera.clone());
+        }
+
+        let stride = canvas.len() / self.canvas_height as usize;
+        let width = self.area.width as usize;
+        let mirror = self.settings.mirror();
+        for (y, mask) in self.mask.chunks_exact(width).enumerate() {
+            let o = (self.area.y as usize + y) * stride + 4 * self.area.x as usize;
+            let out = &mut canvas[o..o + 4 * width];
+            let row = &self.scaled[4 * y * width..4 * (y + 1) * width];
+            for (x, (pixel, &alpha)) in out.chunks_exact_mut(4).zip(mask).enumerate() {
+                let x = if mirror { width - 1 - x } else { x };
+                let src = &row[4 * x..4 * x + 4];
+                match alpha {
+                    0 => {}
+                    255 => pixel[..3].copy_from_slice(&src[..3]),
+                    alpha => {
+                        let alpha = alpha as u32;
+                        for (d, &s) in pixel[..3].iter_mut().zip(src) {
+                            *d = ((s as u32 * alpha + *d as u32 * (255 - alpha) + 127) / 255) as u8;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sizes and places the overlay for a camera of `camera_width` x
+    /// `camera_height`.
+    fn layout(&mut self, camera_width: u32, camera_height: u32) {
+        self.camera_size = (camera_width, camera_height);
+        self.last = None;
+        self.scaler = None;
+
+        let margin = self.settings.margin();
+        let max_width = self.canvas_width.saturating_sub(2 * margin);
+        let max_height = self.canvas_height.saturating_sub(2 * margin);
+        let shape = self.settings.shape();
+        let mut width = (self.canvas_width as f32 * self.settings.size()) as u32;
+        let mut height = match shape {
+            OverlayShape::Circle => width,
+            OverlayShape::Rounded => width * camera_height / camera_width,
+        };
+        // Shrink to fit, keeping the aspect ratio.
+        if width > max_width {
+            height = height * max_width / width;
+            width = max_width;
+        }
+        if height > max_height {
+            width = width * max_height / height.max(1);
+            height = max_height;
+        }
+        let width = width & !1;
+        let height = height & !1;
+        if width < 2 || height < 2 {
+            println!("no room for the webcam overlay");
+            return;
+        }
+
+        let (x, y) = match self.settings.corner() {
+            Corner::TopLeft => (margin, margin),
+            Corner::TopRight => (self.canvas_width - margin - width, margin),
+            Corner::BottomLeft => (margin, self.canvas_height - margin - height),
+            Corner::BottomRight => (
+                self.canvas_width - margin - width,
+                self.canvas_height - margin - height,
+            ),
+        };
+        self.area = Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+
+        // The middle of the camera picture at the overlay's aspect ratio.
+        let (crop_width, crop_height) =
+            if camera_width as u64 * height as u64 > width as u64 * camera_height as u64 {
+                (width * camera_height / height, camera_height)
+            } else {
+                (camera_width, height * camera_width / width)
+            };
+        let crop_width = crop_width.clamp(1, camera_width);
+        let crop_height = crop_height.clamp(1, camera_height);
+        self.crop = Rect {
+            x: (camera_width - crop_width) / 2,
+            y: (camera_height - crop_height) / 2,
+            width: crop_width,
+            height: crop_height,
+        };
+        self.scaler = Some(Scaler::stretch(
+            crop_width,
+            crop_height,
+            width,
+            height,
+            ScaleFilter::Bilinear,
+        ));
+
+        let radius = match shape {
+            OverlayShape::Circle => width as f32 / 2.0,
+            OverlayShape::Rounded => self.settings.corner_radius() as f32,
+        };
+        self.mask = mask(width, height, radius);
+    }
+}
+
+/// Coverage of a `width` x `height` rectangle with corners rounded by
+/// `radius`, anti-aliased over about a pixel.
+fn mask(width: u32, height: u32, radius: f32) -> Vec<u8> {
+    let half_width = width as f32 / 2.0;
+    let half_height = height as f32 / 2.0;
+    let radius = radius.clamp(0.0, half_width.min(half_height));
+    let mut mask = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        let dy = (y as f32 + 0.5 - half_height).abs() - (half_height - radius);
+        for x in 0..width {
+            let dx = (x as f32 + 0.5 - half_width).abs() - (half_width - radius);
+            // Distance outside the rounded edge, negative inside.
+            let outside = dx.max(0.0).hypot(dy.max(0.0)) + dx.max(dy).min(0.0) - radius;
+            mask.push(((0.5 - outside).clamp(0.0, 1.0) * 255.0).round() as u8);
+        }
+    }
+    mask
+}
@@ -1,52 +1,54 @@
-pub fn argb_to_i420(width: usize, height: usize, src: &[u8], dest: &mut Vec<u8>) {
-    let stride = src.len() / height;
+use super::i420::Matrix;
 
-    dest.clear();
-
-    for y in 0..height {
-        for x in 0..width {
-            let o = y * stride + 4 * x;
-
-            let b = src[o] as i32;
-            let g = src[o + 1] as i32;
-            let r = src[o + 2] as i32;
-
-            let y = (66 * r + 129 * g + 25 * b + 128) / 256 + 16;
-            dest.push(clamp(y));
-        }
-    }
-
-    for y in (0..height).step_by(2) {
-        for x in (0..width).step_by(2) {
-            let o = y * stride + 4 * x;
-
-            let b = src[o] as i32;
-            let g = src[o + 1] as i32;
-            let r = src[o + 2] as i32;
-
-            let u = (-38 * r - 74 * g + 112 * b + 128) / 256 + 128;
-            dest.push(clamp(u));
-        }
-    }
+/// Converts a packed ARGB frame to I420 with the given colour `matrix`.
+///
+/// Row bands are converted on up to `threads` threads with SIMD kernels, see
+/// `i420`.
+pub fn argb_to_i420(
+    width: usize,
+    height: usize,
+    src: &[u8],
+    matrix: &Matrix,
+    threads: usize,
+    dest: &mut Vec<u8>,
+) {
+    super::i420::convert(width, height, src, matrix, threads, dest);
+}
 
-    for y in (0..height).step_by(2) {
-        for x in (0..width).step_by(2) {
-            let o = y * stride + 4 * x;
+/// Copies the `width` x `height` block at (`x`, `y`) out of a packed ARGB frame.
+///
+/// Parts of the block outside the source are filled with black, so a window
+/// that shrinks mid-session still yields a full-size frame.
+pub fn crop_argb(
+    src: &[u8],
+    src_width: usize,
+    src_height: usize,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    dest: &mut Vec<u8>,
+) {
+    let stride = src.len() / src_height;
+    let copy_width = src_width.saturating_sub(x).min(width);
 
-            let b = src[o] as i32;
-            let g = src[o + 1] as i32;
-            let r = src[o + 2] as i32;
+    dest.clear();
 
-            let v = (112 * r - 94 * g - 18 * b + 128) / 256 + 128;
-            dest.push(clamp(v));
+    for row in y..y + height {
+        if row < src_height && copy_width > 0 {
+            let o = row * stride + 4 * x;
+            dest.extend_from_slice(&src[o..o + 4 * copy_width]);
+            dest.extend(
+                std::iter::repeat([0, 0, 0, 255])
+                    .take(width - copy_width)
+                    .flatten(),
+            );
+        } else {
+            dest.extend(std::iter::repeat([0, 0, 0, 255]).take(width).flatten());
         }
     }
 }
 
-fn clamp(x: i32) -> u8 {
-    x.min(255).max(0) as u8
-}
-
 #[cfg(feature = "use_dasp")]
 pub fn audio_resample(
     data: &[f32],
@@ -0,0 +1,86 @@
+/// Where the mouse pointer was when a frame was captured.
+#[derive(Debug, Clone, Copy)]
+pub struct Pointer {
+    /// Tip of the arrow in captured pixels, may be outside the frame.
+    pub x: i32,
+    pub y: i32,
+    /// Pixels per point of the monitor under it, the arrow is drawn this much
+    /// larger.
+    pub scale: f32,
+}
+
+// The captured pixels carry no pointer and its shape can't be read portably,
+// so a standard arrow is drawn instead: `X` is the outline, `.` the fill and
+// the tip is the top left corner.
+const ARROW: [&str; 20] = [
+    "X",
+    "XX",
+    "X.X",
+    "X..X",
+    "X...X",
+    "X....X",
+    "X.....X",
+    "X......X",
+    "X.......X",
+    "X........X",
+    "X.........X",
+    "X..........X",
+    "X......XXXXX",
+    "X...X..X",
+    "X..XX..X",
+    "X.X  X..X",
+    "XX   X..X",
+    "X     X..X",
+    "      X..X",
+    "       XX",
+];
+const ARROW_WIDTH: usize = 12;
+
+// BGRA in memory, like the captured frames.
+const OUTLINE: [u8; 3] = [0, 0, 0];
+const FILL: [u8; 3] = [255, 255, 255];
+
+/// Draws `pointer` onto a packed ARGB frame of `width` x `height` whose top
+/// left corner is at (`left`, `top`) in captured pixels.
+pub fn draw(pointer: &Pointer, frame: &mut [u8], width: usize, height: usize, left: u32, top: u32) {
+    if width == 0 || height == 0 {
+        return;
+    }
+    let scale = pointer.scale.max(1.0);
+    let origin_x = pointer.x as i64 - left as i64;
+    let origin_y = pointer.y as i64 - top as i64;
+    let arrow_width = (ARROW_WIDTH as f32 * scale).ceil() as i64;
+    let arrow_height = (ARROW.len() as f32 * scale).ceil() as i64;
+    if origin_x >= width as i64
+        || origin_y >= height as i64
+        || origin_x + arrow_width <= 0
+        || origin_y + arrow_height <= 0
+    {
+        return;
+    }
+
+    let stride = frame.len() / height;
+    for dy in 0..arrow_height {
+        let y = origin_y + dy;
+        if y < 0 || y >= height as i64 {
+            continue;
+        }
+        let Some(row) = ARROW.get((dy as f32 / scale) as usize) else {
+            break;
+        };
+        let row = row.as_bytes();
+        for dx in 0..arrow_width {
+            let x = origin_x + dx;
+            if x < 0 || x >= width as i64 {
+                continue;
+            }
+            let color = match row.get((dx as f32 / scale) as usize) {
+                Some(b'X') => OUTLINE,
+                Some(b'.') => FILL,
+                _ => continue,
+            };
+            let o = y as usize * stride + 4 * x as usize;
+            frame[o..o + 3].copy_from_slice(&color);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use std::fmt;
+
+/// Why a recording session could not start or ended early.
+#[derive(Debug)]
+pub enum RecorderError {
+    /// The screen or window could not be captured.
+    Capture(String),
+    /// An audio device could not be opened or started.
+    Audio(String),
+    /// The video or audio encoder failed.
+    Encoder(String),
+    /// The output file could not be created or written.
+    Output(String),
+    /// A pipeline thread panicked.
+    Worker(&'static str),
+}
+
+pub type RecorderResult<T> = Result<T, RecorderError>;
+
+impl fmt::Display for RecorderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecorderError::Capture(e) => write!(f, "screen capture failed: {}", e),
+            RecorderError::Audio(e) => write!(f, "audio capture failed: {}", e),
+            RecorderError::Encoder(e) => write!(f, "encoding failed: {}", e),
+            RecorderError::Output(e) => write!(f, "writing the recording failed: {}", e),
+            RecorderError::Worker(name) => write!(f, "the {} thread stopped unexpectedly", name),
+        }
+    }
+}
+
+impl std::error::Error for RecorderError {}
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+use tauri_specta::Event;
+
+use super::{ConverterStats, FrameStats, ReorderStats};
+use crate::APP_HANDLE;
+
+#[derive(Serialize, Deserialize, Debug, Clone, specta::Type, tauri_specta::Event)]
+pub struct RecordingStarted {
+    pub path: String,
+}
+
+/// Emitted on pause and on resume, `paused` tells which.
+#[derive(Serialize, Deserialize, Debug, Clone, specta::Type, tauri_specta::Event)]
+pub struct RecordingPaused {
+    pub paused: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, specta::Type, tauri_specta::Event)]
+pub struct RecordingStopped {
+    pub path: String,
+    /// Recorded duration in seconds, paused time excluded.
+    pub duration: f64,
+    #[specta(type = f64)]
+    pub bytes: u64,
+    pub frames: FrameStats,
+    pub converters: Vec<ConverterStats>,
+    pub reorder: ReorderStats,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, specta::Type, tauri_specta::Event)]
+pub struct RecordingFailed {
+    pub reason: String,
+}
+
+pub fn emit<E: Event + Serialize + Clone>(event: E) {
+    if let Some(handle) = APP_HANDLE.get() {
+        event.emit(handle).unwrap_or_default();
+    }
+}
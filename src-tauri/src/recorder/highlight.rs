@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use rdev::Button;
+
+use super::cursor::Pointer;
+use super::input::{InputKind, InputListener, KeyCombo};
+use super::paint::{self, Canvas};
+use super::source::CaptureSource;
+use crate::config::{InputOverlay, OverlayPosition};
+
+/// Combos shown in a caption at most, the oldest go first.
+const CAPTION_COMBOS: usize = 3;
+
+// BGR, like the frames.
+const LEFT_CLICK: [u8; 3] = [7, 193, 255];
+const RIGHT_CLICK: [u8; 3] = [255, 140, 50];
+const OTHER_CLICK: [u8; 3] = [80, 200, 80];
+const CAPTION_BACKGROUND: [u8; 3] = [32, 32, 32];
+const CAPTION_TEXT: [u8; 3] = [255, 255, 255];
+
+/// Click ripples and the key caption to draw on one frame.
+pub struct Effects {
+    pub ripples: Vec<Ripple>,
+    pub caption: Option<Caption>,
+}
+
+pub struct Ripple {
+    /// Where the click was, in captured pixels.
+    pub at: Pointer,
+    pub button: Button,
+    /// How far the ripple has grown and faded, 0 to 1.
+    pub progress: f32,
+}
+
+pub struct Caption {
+    pub text: String,
+    pub opacity: f32,
+    pub position: OverlayPosition,
+    /// Gap to the edges, in output pixels.
+    pub margin: u32,
+}
+
+/// Keeps recent input and works out what shows on each frame.
+///
+/// It runs in the producer, where frames are still in capture order, and
+/// hands each frame its effects; the converters only draw them.
+pub struct InputTracker {
+    settings: InputOverlay,
+    listener: InputListener,
+    /// Clicks in captured pixels, oldest first.
+    clicks: VecDeque<(Instant, Pointer, Button)>,
+    /// Labels of the combos to caption, oldest first.
+    keys: VecDeque<(Instant, String)>,
+}
+
+impl InputTracker {
+    /// Starts listening to global input.
+    pub fn new(settings: &InputOverlay) -> Self {
+        Self {
+            settings: settings.clone(),
+            listener: InputListener::start(),
+            clicks: VecDeque::new(),
+            keys: VecDeque::new(),
+        }
+    }
+
+    /// What to draw on a frame of `source` captured at `at`, `None` when
+    /// there is nothing.
+    pub fn effects(&mut self, at: Instant, source: &CaptureSource) -> Option<Effects> {
+        for event in self.listener.events() {
+            match event.kind {
+                InputKind::Click { x, y, button } if self.settings.clicks() => {
+                    if let Some(point) = source.locate(x, y) {
+                        self.clicks.push_back((event.at, point, button));
+                    }
+                }
+                InputKind::Keys(combo) if self.settings.keys() && self.allowed(&combo) => {
+                    self.keys.push_back((event.at, combo.label));
+                }
+                _ => {}
+            }
+        }
+
+        // Events arrive in order, so the expired ones are at the front.
+        let click_duration = self.settings.click_duration();
+        while self
+            .clicks
+            .front()
+            .is_some_and(|(t, ..)| at.saturating_duration_since(*t) >= click_duration)
+        {
+            self.clicks.pop_front();
+        }
+        let caption_duration = self.settings.caption_duration();
+        while self
+            .keys
+            .front()
+            .is_some_and(|(t, _)| at.saturating_duration_since(*t) >= caption_duration)
+        {
+            self.keys.pop_front();
+        }
+
+        // Input after the frame was taken shows from the next one.
+        let ripples: Vec<Ripple> = self
+            .clicks
+            .iter()
+            .filter_map(|&(t, point, button)| {
+                let age = at.checked_duration_since(t)?;
+                Some(Ripple {
+                    at: point,
+                    button,
+                    progress: age.as_secs_f32() / click_duration.as_secs_f32(),
+                })
+            })
+            .collect();
+
+        let shown: Vec<&(Instant, String)> = self.keys.iter().filter(|(t, _)| *t <= at).collect();
+        let caption = shown.last().map(|(last, _)| {
+            let left = caption_duration.saturating_sub(at - *last);
+            let fade = self.settings.caption_fade();
+            let opacity = if fade.is_zero() {
+                1.0
+            } else {
+                (left.as_secs_f32() / fade.as_secs_f32()).min(1.0)
+            };
+            let first = shown.len().saturating_sub(CAPTION_COMBOS);
+            let text = shown[first..]
+                .iter()
+                .map(|(_, label)| label.as_str())
+                .collect::<Vec<_>>()
+                .join("  ");
+            Caption {
+                text,
+                opacity,
+                position: self.settings.caption_position(),
+                margin: self.settings.margin(),
+            }
+        });
+
+        if ripples.is_empty() && caption.is_none() {
+            return None;
+        }
+        Some(Effects { ripples, caption })
+    }
+
+    fn allowed(&self, combo: &KeyCombo) -> bool {
+        match &self.settings.key_allowlist {
+            Some(allowlist) => allowlist
+                .iter()
+                .any(|allowed| same_combo(allowed, &combo.label)),
+            None => combo.named,
+        }
+    }
+}
+
+/// Compares combos ignoring case and spaces, so `ctrl + s` matches `Ctrl+S`.
+fn same_combo(a: &str, b: &str) -> bool {
+    let a = a.chars().filter(|c| !c.is_whitespace());
+    let b = b.chars().filter(|c| !c.is_whitespace());
+    a.map(|c| c.to_ascii_lowercase())
+        .eq(b.map(|c| c.to_ascii_lowercase()))
+}
+
+/// Draws click ripples onto a frame whose top left is at (`left`, `top`) in
+/// captured pixels.
+pub fn draw_ripples(ripples: &[Ripple], canvas: &mut Canvas, left: u32, top: u32) {
+    for ripple in ripples {
+        let scale = ripple.at.scale.max(1.0);
+        // Grows quickly at first and slows down as it fades.
+        let grown = 1.0 - (1.0 - ripple.progress).powi(2);
+        let radius = (6.0 + 22.0 * grown) * scale;
+        let half_width = 1.5 * scale;
+        let alpha = 0.8 * (1.0 - ripple.progress);
+        let color = match ripple.button {
+            Button::Left => LEFT_CLICK,
+            Button::Right => RIGHT_CLICK,
+            _ => OTHER_CLICK,
+        };
+
+        let cx = ripple.at.x as f32 - left as f32;
+        let cy = ripple.at.y as f32 - top as f32;
+        let reach = (radius + half_width + 1.0).ceil() as i64;
+        for y in cy as i64 - reach..=cy as i64 + reach {
+            for x in cx as i64 - reach..=cx as i64 + reach {
+                let distance = (x as f32 + 0.5 - cx).hypot(y as f32 + 0.5 - cy);
+                // Anti-aliased over about a pixel on both edges of the ring.
+                let coverage = (half_width + 0.5 - (distance - radius).abs()).clamp(0.0, 1.0);
+                if coverage > 0.0 {
+                    canvas.blend(x, y, color, alpha * coverage);
+                }
+            }
+        }
+    }
+}
+
+/// Draws the key caption onto the output frame.
+pub fn draw_caption(caption: &Caption, canvas: &mut Canvas) {
+    // Glyphs about 3% of the frame's height.
+    let scale = (canvas.height() as u32 / 270).max(2);
+    let padding = 3 * scale;
+    let (text_width, text_height) = paint::text_size(&caption.text, scale);
+    let width = text_width + 2 * padding;
+    let height = text_height + 2 * padding;
+
+    let (x, y) = paint::place(
+        caption.position,
+        caption.margin,
+        (width, height),
+        (canvas.width(), canvas.height()),
+    );
+
+    canvas.fill(
+        x,
+        y,
+        width,
+        height,
+        CAPTION_BACKGROUND,
+        0.7 * caption.opacity,
+    );
+    canvas.text(
+        x + padding as i64,
+        y + padding as i64,
+        &caption.text,
+        scale,
+        CAPTION_TEXT,
+        caption.opacity,
+    );
+}
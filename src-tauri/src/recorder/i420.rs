@@ -0,0 +1,699 @@
+//! ARGB → I420 conversion, split into row bands converted in parallel when
+//! the caller has threads to spare.
+//!
+//! The row kernels use SSE2, AVX2 or NEON when the CPU has them and fall back
+//! to scalar code otherwise. All of them reproduce the scalar formulas exactly,
+//! including the division that truncates toward zero for negative chroma, so
+//! the output does not depend on the machine that recorded it.
+
+use std::sync::OnceLock;
+
+use crate::config::ColorRange;
+
+// Below this many rows per band another thread costs more than it saves.
+const MIN_BAND_ROWS: usize = 128;
+// The converters already run one frame per core, so bands stay few.
+const MAX_BANDS: usize = 4;
+
+/// YUV matrix frames are converted with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMatrix {
+    Bt601,
+    Bt709,
+}
+
+impl ColorMatrix {
+    /// The matrix for output `height` rows tall.
+    ///
+    /// The WebM binding can only tag the range, so players pick the matrix
+    /// from the frame size, BT.709 from 720 rows up and BT.601 below. Any
+    /// other choice would play back with the wrong colours, which is why it
+    /// is not configurable.
+    pub fn for_height(height: u32) -> Self {
+        if height >= 720 {
+            ColorMatrix::Bt709
+        } else {
+            ColorMatrix::Bt601
+        }
+    }
+}
+
+/// Fixed-point conversion coefficients, scaled by 256, in r, g, b order.
+///
+/// Luma weights sum to at most 256 and each sign of a chroma row to at most
+/// 128, which keeps every kernel sum within 16 bits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix {
+    y: [i16; 3],
+    y_offset: i16,
+    u: [i16; 3],
+    v: [i16; 3],
+}
+
+impl Matrix {
+    pub fn new(matrix: ColorMatrix, range: ColorRange) -> Self {
+        match (matrix, range) {
+            (ColorMatrix::Bt601, ColorRange::Limited) => Matrix {
+                y: [66, 129, 25],
+                y_offset: 16,
+                u: [-38, -74, 112],
+                v: [112, -94, -18],
+            },
+            (ColorMatrix::Bt601, ColorRange::Full) => Matrix {
+                y: [77, 150, 29],
+                y_offset: 0,
+                u: [-43, -85, 128],
+                v: [128, -107, -21],
+            },
+            (ColorMatrix::Bt709, ColorRange::Limited) => Matrix {
+                y: [47, 157, 16],
+                y_offset: 16,
+                u: [-26, -86, 112],
+                v: [112, -102, -10],
+            },
+            (ColorMatrix::Bt709, ColorRange::Full) => Matrix {
+                y: [54, 183, 19],
+                y_offset: 0,
+                u: [-29, -99, 128],
+                v: [128, -116, -12],
+            },
+        }
+    }
+}
+
+/// Converts one row of `dst.len()` pixels to luma.
+type YRow = unsafe fn(m: &Matrix, src: &[u8], dst: &mut [u8]);
+/// Converts the even pixels of one row to `u.len()` chroma samples.
+type UvRow = unsafe fn(m: &Matrix, src: &[u8], u: &mut [u8], v: &mut [u8]);
+
+#[derive(Clone, Copy)]
+struct Kernels {
+    y_row: YRow,
+    uv_row: UvRow,
+}
+
+fn kernels() -> Kernels {
+    static KERNELS: OnceLock<Kernels> = OnceLock::new();
+    *KERNELS.get_or_init(|| {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Kernels {
+                    y_row: x86::y_row_avx2,
+                    uv_row: x86::uv_row_avx2,
+                };
+            }
+            if is_x86_feature_detected!("sse2") {
+                return Kernels {
+                    y_row: x86::y_row_sse2,
+                    uv_row: x86::uv_row_sse2,
+                };
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                return Kernels {
+                    y_row: neon::y_row,
+                    uv_row: neon::uv_row,
+                };
+            }
+        }
+        Kernels {
+            y_row: y_row_scalar,
+            uv_row: uv_row_scalar,
+        }
+    })
+}
+
+/// Converts a packed ARGB (BGRA in memory) frame into a planar I420 `dest`,
+/// on up to `threads` threads.
+///
+/// Chroma is taken from the top-left pixel of every 2x2 block.
+pub fn convert(
+    width: usize,
+    height: usize,
+    src: &[u8],
+    matrix: &Matrix,
+    threads: usize,
+    dest: &mut Vec<u8>,
+) {
+    convert_with(kernels(), width, height, src, matrix, threads, dest);
+}
+
+fn convert_with(
+    kernels: Kernels,
+    width: usize,
+    height: usize,
+    src: &[u8],
+    matrix: &Matrix,
+    threads: usize,
+    dest: &mut Vec<u8>,
+) {
+    if width == 0 || height == 0 {
+        dest.clear();
+        return;
+    }
+    let stride = src.len() / height;
+    let chroma_width = width.div_ceil(2);
+    let chroma_height = height.div_ceil(2);
+    let luma_len = width * height;
+    let chroma_len = chroma_width * chroma_height;
+
+    dest.clear();
+    dest.resize(luma_len + 2 * chroma_len, 0);
+    let (y_plane, chroma) = dest.split_at_mut(luma_len);
+    let (u_plane, v_plane) = chroma.split_at_mut(chroma_len);
+
+    let bands = (height / MIN_BAND_ROWS)
+        .clamp(1, MAX_BANDS)
+        .min(threads.max(1));
+    // Bands start on even rows so every chroma row belongs to one band.
+    let band_rows = (height.div_ceil(bands) + 1) & !1;
+
+    let band = |first_row: usize, y: &mut [u8], u: &mut [u8], v: &mut [u8]| {
+        let rows = y.len() / width;
+        for row in 0..rows {
+            let o = (first_row + row) * stride;
+            let src_row = &src[o..o + 4 * width];
+            let y_row = &mut y[row * width..(row + 1) * width];
+            // SAFETY: `kernels` only holds kernels the CPU supports and
+            // `src_row` holds the `width` pixels the kernels read.
+            unsafe { (kernels.y_row)(matrix, src_row, y_row) };
+            if row % 2 == 0 {
+                let c = row / 2 * chroma_width;
+                let u_row = &mut u[c..c + chroma_width];
+                let v_row = &mut v[c..c + chroma_width];
+                unsafe { (kernels.uv_row)(matrix, src_row, u_row, v_row) };
+            }
+        }
+    };
+
+    if bands == 1 {
+        band(0, y_plane, u_plane, v_plane);
+        return;
+    }
+
+    std::thread::scope(|scope| {
+        let y_bands = y_plane.chunks_mut(band_rows * width);
+        let u_bands = u_plane.chunks_mut(band_rows / 2 * chroma_width);
+        let v_bands = v_plane.chunks_mut(band_rows / 2 * chroma_width);
+        for (i, ((y, u), v)) in y_bands.zip(u_bands).zip(v_bands).enumerate() {
+            let band = &band;
+            scope.spawn(move || band(i * band_rows, y, u, v));
+        }
+    });
+}
+
+#[inline]
+fn dot(c: [i16; 3], b: i32, g: i32, r: i32) -> i32 {
+    c[0] as i32 * r + c[1] as i32 * g + c[2] as i32 * b
+}
+
+#[inline]
+fn luma(m: &Matrix, b: i32, g: i32, r: i32) -> u8 {
+    clamp((dot(m.y, b, g, r) + 128) / 256 + m.y_offset as i32)
+}
+
+#[inline]
+fn chroma(m: &Matrix, b: i32, g: i32, r: i32) -> (u8, u8) {
+    let u = (dot(m.u, b, g, r) + 128) / 256 + 128;
+    let v = (dot(m.v, b, g, r) + 128) / 256 + 128;
+    (clamp(u), clamp(v))
+}
+
+#[inline]
+fn clamp(x: i32) -> u8 {
+    x.clamp(0, 255) as u8
+}
+
+unsafe fn y_row_scalar(m: &Matrix, src: &[u8], dst: &mut [u8]) {
+    y_tail(m, src, dst, 0);
+}
+
+unsafe fn uv_row_scalar(m: &Matrix, src: &[u8], u: &mut [u8], v: &mut [u8]) {
+    uv_tail(m, src, u, v, 0);
+}
+
+/// Luma for the pixels from `start` on, after a kernel's last full block.
+#[inline]
+fn y_tail(m: &Matrix, src: &[u8], dst: &mut [u8], start: usize) {
+    for (x, y) in dst.iter_mut().enumerate().skip(start) {
+        let o = 4 * x;
+        *y = luma(m, src[o] as i32, src[o + 1] as i32, src[o + 2] as i32);
+    }
+}
+
+/// Chroma for the samples from `start` on, after a kernel's last full block.
+#[inline]
+fn uv_tail(m: &Matrix, src: &[u8], u: &mut [u8], v: &mut [u8], start: usize) {
+    for (x, (u, v)) in u.iter_mut().zip(v.iter_mut()).enumerate().skip(start) {
+        let o = 8 * x;
+        (*u, *v) = chroma(m, src[o] as i32, src[o + 1] as i32, src[o + 2] as i32);
+    }
+}
+
+// The kernels compute in 16-bit lanes. Luma sums are unsigned and at most
+// 256 * 255 + 128; chroma sums are signed and within +-128 * 255, but adding
+// the rounding term would overflow i16, so `(x + 128) / 256` is done as a
+// floor division of the two halves of `x` plus a fix-up toward zero.
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    use super::{uv_tail, y_tail, Matrix};
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn channels_sse2(p0: __m128i, p1: __m128i) -> (__m128i, __m128i, __m128i) {
+        let mask = _mm_set1_epi32(0xff);
+        let b = _mm_packs_epi32(_mm_and_si128(p0, mask), _mm_and_si128(p1, mask));
+        let g = _mm_packs_epi32(
+            _mm_and_si128(_mm_srli_epi32(p0, 8), mask),
+            _mm_and_si128(_mm_srli_epi32(p1, 8), mask),
+        );
+        let r = _mm_packs_epi32(
+            _mm_and_si128(_mm_srli_epi32(p0, 16), mask),
+            _mm_and_si128(_mm_srli_epi32(p1, 16), mask),
+        );
+        (b, g, r)
+    }
+
+    /// The even pixels of the eight in `a` and `b`.
+    #[target_feature(enable = "sse2")]
+    unsafe fn even_sse2(a: __m128i, b: __m128i) -> __m128i {
+        _mm_unpacklo_epi64(
+            _mm_shuffle_epi32(a, 0b10_00_10_00),
+            _mm_shuffle_epi32(b, 0b10_00_10_00),
+        )
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn dot_sse2(c: [i16; 3], b: __m128i, g: __m128i, r: __m128i) -> __m128i {
+        _mm_add_epi16(
+            _mm_add_epi16(
+                _mm_mullo_epi16(r, _mm_set1_epi16(c[0])),
+                _mm_mullo_epi16(g, _mm_set1_epi16(c[1])),
+            ),
+            _mm_mullo_epi16(b, _mm_set1_epi16(c[2])),
+        )
+    }
+
+    /// `(x + 128) / 256 + 128` rounding toward zero, like the scalar chroma.
+    #[target_feature(enable = "sse2")]
+    unsafe fn chroma_sse2(x: __m128i) -> __m128i {
+        let half = _mm_set1_epi16(128);
+        let low = _mm_add_epi16(_mm_and_si128(x, _mm_set1_epi16(255)), half);
+        let floor = _mm_add_epi16(_mm_srai_epi16(x, 8), _mm_srli_epi16(low, 8));
+        let negative = _mm_cmplt_epi16(x, _mm_set1_epi16(-128));
+        let exact = _mm_cmpeq_epi16(
+            _mm_and_si128(_mm_add_epi16(x, half), _mm_set1_epi16(255)),
+            _mm_setzero_si128(),
+        );
+        // The masks are -1, so subtracting them rounds up.
+        let up = _mm_andnot_si128(exact, negative);
+        _mm_add_epi16(_mm_sub_epi16(floor, up), half)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn y_row_sse2(m: &Matrix, src: &[u8], dst: &mut [u8]) {
+        let width = dst.len();
+        let mut x = 0;
+        while x + 8 <= width {
+            let s = src.as_ptr().add(4 * x) as *const __m128i;
+            let (b, g, r) = channels_sse2(_mm_loadu_si128(s), _mm_loadu_si128(s.add(1)));
+            let y = _mm_add_epi16(dot_sse2(m.y, b, g, r), _mm_set1_epi16(128));
+            let y = _mm_add_epi16(_mm_srli_epi16(y, 8), _mm_set1_epi16(m.y_offset));
+            _mm_storel_epi64(
+                dst.as_mut_ptr().add(x) as *mut __m128i,
+                _mm_packus_epi16(y, y),
+            );
+            x += 8;
+        }
+        y_tail(m, src, dst, x);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn uv_row_sse2(m: &Matrix, src: &[u8], u: &mut [u8], v: &mut [u8]) {
+        let width = u.len();
+        let mut x = 0;
+        // Eight chroma samples come from the even pixels of sixteen.
+        while x + 8 <= width && 8 * (x + 8) <= src.len() {
+            let s = src.as_ptr().add(8 * x) as *const __m128i;
+            let p0 = even_sse2(_mm_loadu_si128(s), _mm_loadu_si128(s.add(1)));
+            let p1 = even_sse2(_mm_loadu_si128(s.add(2)), _mm_loadu_si128(s.add(3)));
+            let (b, g, r) = channels_sse2(p0, p1);
+
+            let cu = chroma_sse2(dot_sse2(m.u, b, g, r));
+            let cv = chroma_sse2(dot_sse2(m.v, b, g, r));
+            _mm_storel_epi64(
+                u.as_mut_ptr().add(x) as *mut __m128i,
+                _mm_packus_epi16(cu, cu),
+            );
+            _mm_storel_epi64(
+                v.as_mut_ptr().add(x) as *mut __m128i,
+                _mm_packus_epi16(cv, cv),
+            );
+            x += 8;
+        }
+        uv_tail(m, src, u, v, x);
+    }
+
+    /// Packs two vectors of eight i32 into sixteen i16, keeping their order.
+    #[target_feature(enable = "avx2")]
+    unsafe fn pack_i32_avx2(a: __m256i, b: __m256i) -> __m256i {
+        _mm256_permute4x64_epi64(_mm256_packs_epi32(a, b), 0b11_01_10_00)
+    }
+
+    /// Stores sixteen i16, saturated to u8, at `dst`.
+    #[target_feature(enable = "avx2")]
+    unsafe fn store_u8_avx2(dst: *mut u8, x: __m256i) {
+        let packed = _mm256_permute4x64_epi64(_mm256_packus_epi16(x, x), 0b11_01_10_00);
+        _mm_storeu_si128(dst as *mut __m128i, _mm256_castsi256_si128(packed));
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn channels_avx2(p0: __m256i, p1: __m256i) -> (__m256i, __m256i, __m256i) {
+        let mask = _mm256_set1_epi32(0xff);
+        let b = pack_i32_avx2(_mm256_and_si256(p0, mask), _mm256_and_si256(p1, mask));
+        let g = pack_i32_avx2(
+            _mm256_and_si256(_mm256_srli_epi32(p0, 8), mask),
+            _mm256_and_si256(_mm256_srli_epi32(p1, 8), mask),
+        );
+        let r = pack_i32_avx2(
+            _mm256_and_si256(_mm256_srli_epi32(p0, 16), mask),
+            _mm256_and_si256(_mm256_srli_epi32(p1, 16), mask),
+        );
+        (b, g, r)
+    }
+
+    /// The even pixels of the sixteen in `a` and `b`.
+    #[target_feature(enable = "avx2")]
+    unsafe fn even_avx2(a: __m256i, b: __m256i) -> __m256i {
+        let evens = _mm256_setr_epi32(0, 2, 4, 6, 1, 3, 5, 7);
+        _mm256_permute2x128_si256(
+            _mm256_permutevar8x32_epi32(a, evens),
+            _mm256_permutevar8x32_epi32(b, evens),
+            0x20,
+        )
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn dot_avx2(c: [i16; 3], b: __m256i, g: __m256i, r: __m256i) -> __m256i {
+        _mm256_add_epi16(
+            _mm256_add_epi16(
+                _mm256_mullo_epi16(r, _mm256_set1_epi16(c[0])),
+                _mm256_mullo_epi16(g, _mm256_set1_epi16(c[1])),
+            ),
+            _mm256_mullo_epi16(b, _mm256_set1_epi16(c[2])),
+        )
+    }
+
+    /// `(x + 128) / 256 + 128` rounding toward zero, like the scalar chroma.
+    #[target_feature(enable = "avx2")]
+    unsafe fn chroma_avx2(x: __m256i) -> __m256i {
+        let half = _mm256_set1_epi16(128);
+        let low = _mm256_add_epi16(_mm256_and_si256(x, _mm256_set1_epi16(255)), half);
+        let floor = _mm256_add_epi16(_mm256_srai_epi16(x, 8), _mm256_srli_epi16(low, 8));
+        let negative = _mm256_cmpgt_epi16(_mm256_set1_epi16(-128), x);
+        let exact = _mm256_cmpeq_epi16(
+            _mm256_and_si256(_mm256_add_epi16(x, half), _mm256_set1_epi16(255)),
+            _mm256_setzero_si256(),
+        );
+        // The masks are -1, so subtracting them rounds up.
+        let up = _mm256_andnot_si256(exact, negative);
+        _mm256_add_epi16(_mm256_sub_epi16(floor, up), half)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn y_row_avx2(m: &Matrix, src: &[u8], dst: &mut [u8]) {
+        let width = dst.len();
+        let mut x = 0;
+        while x + 16 <= width {
+            let s = src.as_ptr().add(4 * x) as *const __m256i;
+            let (b, g, r) = channels_avx2(_mm256_loadu_si256(s), _mm256_loadu_si256(s.add(1)));
+            let y = _mm256_add_epi16(dot_avx2(m.y, b, g, r), _mm256_set1_epi16(128));
+            let y = _mm256_add_epi16(_mm256_srli_epi16(y, 8), _mm256_set1_epi16(m.y_offset));
+            store_u8_avx2(dst.as_mut_ptr().add(x), y);
+            x += 16;
+        }
+        y_tail(m, src, dst, x);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn uv_row_avx2(m: &Matrix, src: &[u8], u: &mut [u8], v: &mut [u8]) {
+        let width = u.len();
+        let mut x = 0;
+        // Sixteen chroma samples come from the even pixels of thirty-two.
+        while x + 16 <= width && 8 * (x + 16) <= src.len() {
+            let s = src.as_ptr().add(8 * x) as *const __m256i;
+            let p0 = even_avx2(_mm256_loadu_si256(s), _mm256_loadu_si256(s.add(1)));
+            let p1 = even_avx2(_mm256_loadu_si256(s.add(2)), _mm256_loadu_si256(s.add(3)));
+            let (b, g, r) = channels_avx2(p0, p1);
+
+            store_u8_avx2(u.as_mut_ptr().add(x), chroma_avx2(dot_avx2(m.u, b, g, r)));
+            store_u8_avx2(v.as_mut_ptr().add(x), chroma_avx2(dot_avx2(m.v, b, g, r)));
+            x += 16;
+        }
+        uv_tail(m, src, u, v, x);
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use super::{uv_tail, y_tail, Matrix};
+
+    #[inline]
+    #[target_feature(enable = "neon")]
+    unsafe fn dot(c: [i16; 3], b: int16x8_t, g: int16x8_t, r: int16x8_t) -> int16x8_t {
+        vmlaq_n_s16(vmlaq_n_s16(vmulq_n_s16(r, c[0]), g, c[1]), b, c[2])
+    }
+
+    /// `(x + 128) / 256 + 128` rounding toward zero, like the scalar chroma.
+    #[inline]
+    #[target_feature(enable = "neon")]
+    unsafe fn chroma(x: int16x8_t) -> int16x8_t {
+        let half = vdupq_n_s16(128);
+        let low = vaddq_s16(vandq_s16(x, vdupq_n_s16(255)), half);
+        let floor = vaddq_s16(vshrq_n_s16::<8>(x), vshrq_n_s16::<8>(low));
+        let negative = vcltq_s16(x, vdupq_n_s16(-128));
+        let exact = vceqq_s16(
+            vandq_s16(vaddq_s16(x, half), vdupq_n_s16(255)),
+            vdupq_n_s16(0),
+        );
+        // The masks are -1, so subtracting them rounds up.
+        let up = vreinterpretq_s16_u16(vbicq_u16(negative, exact));
+        vaddq_s16(vsubq_s16(floor, up), half)
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn y_row(m: &Matrix, src: &[u8], dst: &mut [u8]) {
+        let width = dst.len();
+        let mut x = 0;
+        while x + 8 <= width {
+            let p = vld4_u8(src.as_ptr().add(4 * x));
+            let (b, g, r) = (vmovl_u8(p.0), vmovl_u8(p.1), vmovl_u8(p.2));
+            let c = m.y.map(|c| c as u16);
+            let y = vmlaq_n_u16(vmlaq_n_u16(vmulq_n_u16(r, c[0]), g, c[1]), b, c[2]);
+            let y = vaddq_u16(
+                vshrq_n_u16::<8>(vaddq_u16(y, vdupq_n_u16(128))),
+                vdupq_n_u16(m.y_offset as u16),
+            );
+            vst1_u8(dst.as_mut_ptr().add(x), vqmovn_u16(y));
+            x += 8;
+        }
+        y_tail(m, src, dst, x);
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn uv_row(m: &Matrix, src: &[u8], u: &mut [u8], v: &mut [u8]) {
+        let width = u.len();
+        let mut x = 0;
+        // Eight chroma samples come from the even pixels of sixteen.
+        while x + 8 <= width && 8 * (x + 8) <= src.len() {
+            let p = vld4q_u8(src.as_ptr().add(8 * x));
+            // The low byte of each u16 lane is the even pixel.
+            let even =
+                |c: uint8x16_t| vreinterpretq_s16_u16(vmovl_u8(vmovn_u16(vreinterpretq_u16_u8(c))));
+            let (b, g, r) = (even(p.0), even(p.1), even(p.2));
+
+            vst1_u8(
+                u.as_mut_ptr().add(x),
+                vqmovun_s16(chroma(dot(m.u, b, g, r))),
+            );
+            vst1_u8(
+                v.as_mut_ptr().add(x),
+                vqmovun_s16(chroma(dot(m.v, b, g, r))),
+            );
+            x += 8;
+        }
+        uv_tail(m, src, u, v, x);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The scalar conversion the kernels replaced, with the coefficients
+    /// taken from `m` instead of fixed to limited range BT.601.
+    fn reference(width: usize, height: usize, src: &[u8], m: &Matrix) -> Vec<u8> {
+        let stride = src.len() / height;
+        let pixel = |x: usize, y: usize| {
+            let o = y * stride + 4 * x;
+            (src[o] as i32, src[o + 1] as i32, src[o + 2] as i32)
+        };
+        let weigh = |c: [i16; 3], (b, g, r): (i32, i32, i32)| {
+            c[0] as i32 * r + c[1] as i32 * g + c[2] as i32 * b + 128
+        };
+        let clamp = |x: i32| x.clamp(0, 255) as u8;
+
+        let mut dest = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                dest.push(clamp(weigh(m.y, pixel(x, y)) / 256 + m.y_offset as i32));
+            }
+        }
+        for c in [m.u, m.v] {
+            for y in (0..height).step_by(2) {
+                for x in (0..width).step_by(2) {
+                    dest.push(clamp(weigh(c, pixel(x, y)) / 256 + 128));
+                }
+            }
+        }
+        dest
+    }
+
+    /// Every set of kernels this CPU can run, scalar first.
+    fn all_kernels() -> Vec<(&'static str, Kernels)> {
+        #[allow(unused_mut)]
+        let mut all = vec![(
+            "scalar",
+            Kernels {
+                y_row: y_row_scalar,
+                uv_row: uv_row_scalar,
+            },
+        )];
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("sse2") {
+                all.push((
+                    "sse2",
+                    Kernels {
+                        y_row: x86::y_row_sse2,
+                        uv_row: x86::uv_row_sse2,
+                    },
+                ));
+            }
+            if is_x86_feature_detected!("avx2") {
+                all.push((
+                    "avx2",
+                    Kernels {
+                        y_row: x86::y_row_avx2,
+                        uv_row: x86::uv_row_avx2,
+                    },
+                ));
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                all.push((
+                    "neon",
+                    Kernels {
+                        y_row: neon::y_row,
+                        uv_row: neon::uv_row,
+                    },
+                ));
+            }
+        }
+        all
+    }
+
+    /// A frame of random bytes from xorshift seeded with `seed`, with `pad`
+    /// bytes after each row. With `extremes` the bytes are drawn from values
+    /// near the ends of the range, where rounding and clamping go wrong.
+    fn frame(width: usize, height: usize, pad: usize, seed: u64, extremes: bool) -> Vec<u8> {
+        let mut state = seed;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        (0..(4 * width + pad) * height)
+            .map(|_| {
+                let n = next();
+                if extremes {
+                    [0, 1, 127, 128, 254, 255][(n % 6) as usize]
+                } else {
+                    n as u8
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kernels_match_the_scalar_conversion() {
+        let matrices = [ColorMatrix::Bt601, ColorMatrix::Bt709]
+            .into_iter()
+            .flat_map(|m| [ColorRange::Limited, ColorRange::Full].map(|r| Matrix::new(m, r)));
+        // Odd sizes, widths off the 8 and 16 lane counts, and a frame tall
+        // enough to be split into bands.
+        let sizes = [
+            (1, 1),
+            (2, 2),
+            (3, 5),
+            (7, 3),
+            (15, 4),
+            (17, 9),
+            (33, 7),
+            (64, 2),
+            (129, 131),
+            (250, 300),
+        ];
+        let mut seed = 0x9e37_79b9_7f4a_7c15;
+        let mut out = Vec::new();
+        for matrix in matrices {
+            for (width, height) in sizes {
+                for pad in [0, 4, 12] {
+                    for extremes in [false, true] {
+                        seed += 1;
+                        let src = frame(width, height, pad, seed, extremes);
+                        let expected = reference(width, height, &src, &matrix);
+                        for (name, kernels) in all_kernels() {
+                            for threads in [1, 4] {
+                                convert_with(
+                                    kernels, width, height, &src, &matrix, threads, &mut out,
+                                );
+                                assert!(
+                                    out == expected,
+                                    "{name} differs at {width}x{height}, pad {pad}, \
+                                     {threads} threads, {matrix:?}, seed {seed}"
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn limited_bt601_is_the_original_conversion() {
+        assert_eq!(
+            Matrix::new(ColorMatrix::Bt601, ColorRange::Limited),
+            Matrix {
+                y: [66, 129, 25],
+                y_offset: 16,
+                u: [-38, -74, 112],
+                v: [112, -94, -18],
+            }
+        );
+    }
+}
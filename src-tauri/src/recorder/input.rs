@@ -0,0 +1,247 @@
+use std::sync::{Mutex, Once};
+use std::time::Instant;
+
+use crossbeam::channel::{bounded, Receiver, Sender};
+use rdev::{Button, EventType, Key};
+
+use crate::windows::get_mouse_location;
+
+/// Mouse or keyboard input seen by the global listener.
+#[derive(Debug, Clone)]
+pub struct InputEvent {
+    /// When the listener got it.
+    pub at: Instant,
+    pub kind: InputKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum InputKind {
+    /// A mouse button went down at a desktop point, in the units
+    /// `get_mouse_location` uses.
+    Click {
+        x: i32,
+        y: i32,
+        button: Button,
+    },
+    Keys(KeyCombo),
+}
+
+/// A key going down together with the modifiers held at the time.
+#[derive(Debug, Clone)]
+pub struct KeyCombo {
+    /// Modifiers then the key, like `Ctrl+Shift+P`.
+    pub label: String,
+    /// The key has a name, like `Enter` or `F5`, and types no text itself.
+    pub named: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref SUBSCRIBER: Mutex<Option<Sender<InputEvent>>> = Mutex::new(None);
+}
+static LISTENER: Once = Once::new();
+
+/// Receives global mouse and keyboard input while alive.
+///
+/// The OS hook can't be removed once `rdev` has installed it, so a single
+/// listener thread is started on first use and shared by every recording;
+/// events are dropped while nobody is subscribed.
+pub struct InputListener {
+    receiver: Receiver<InputEvent>,
+}
+
+impl InputListener {
+    pub fn start() -> Self {
+        LISTENER.call_once(|| {
+            std::thread::spawn(listen);
+        });
+        // Several seconds of fast typing, more is dropped.
+        let (sender, receiver) = bounded(256);
+        *SUBSCRIBER.lock().unwrap() = Some(sender);
+        Self { receiver }
+    }
+
+    /// Events that arrived since the last call.
+    pub fn events(&self) -> impl Iterator<Item = InputEvent> + '_ {
+        self.receiver.try_iter()
+    }
+}
+
+impl Drop for InputListener {
+    fn drop(&mut self) {
+        *SUBSCRIBER.lock().unwrap() = None;
+    }
+}
+
+#[derive(Default)]
+struct Modifiers {
+    ctrl: bool,
+    alt: bool,
+    alt_gr: bool,
+    shift: bool,
+    meta: bool,
+}
+
+fn listen() {
+    let mut modifiers = Modifiers::default();
+    let result = rdev::listen(move |event| {
+        let kind = match event.event_type {
+            EventType::KeyPress(key) => {
+                if modifiers.set(key, true) {
+                    return;
+                }
+                match combo(&modifiers, key) {
+                    Some(combo) => InputKind::Keys(combo),
+                    None => return,
+                }
+            }
+            EventType::KeyRelease(key) => {
+                modifiers.set(key, false);
+                return;
+            }
+            EventType::ButtonPress(button) => match get_mouse_location() {
+                Ok((x, y)) => InputKind::Click { x, y, button },
+                Err(_) => return,
+            },
+            _ => return,
+        };
+        if let Some(sender) = SUBSCRIBER.lock().unwrap().as_ref() {
+            let _ = sender.try_send(InputEvent {
+                at: Instant::now(),
+                kind,
+            });
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("input listener failed: {:?}", e);
+    }
+}
+
+impl Modifiers {
+    /// Tracks `key` if it is a modifier, returning whether it was one.
+    fn set(&mut self, key: Key, down: bool) -> bool {
+        let flag = match key {
+            Key::ControlLeft | Key::ControlRight => &mut self.ctrl,
+            Key::Alt => &mut self.alt,
+            Key::AltGr => &mut self.alt_gr,
+            Key::ShiftLeft | Key::ShiftRight => &mut self.shift,
+            Key::MetaLeft | Key::MetaRight => &mut self.meta,
+            _ => return false,
+        };
+        *flag = down;
+        true
+    }
+}
+
+const META: &str = if cfg!(target_os = "macos") {
+    "Cmd"
+} else if cfg!(windows) {
+    "Win"
+} else {
+    "Super"
+};
+
+fn combo(modifiers: &Modifiers, key: Key) -> Option<KeyCombo> {
+    let (name, named) = key_name(key)?;
+    let mut label = String::new();
+    for (held, modifier) in [
+        (modifiers.ctrl, "Ctrl"),
+        (modifiers.alt, "Alt"),
+        (modifiers.alt_gr, "AltGr"),
+        (modifiers.shift, "Shift"),
+        (modifiers.meta, META),
+    ] {
+        if held {
+            label.push_str(modifier);
+            label.push('+');
+        }
+    }
+    label.push_str(name);
+    Some(KeyCombo { label, named })
+}
+
+/// The caption for `key` on a US layout, and whether it is a named key that
+/// types no text. Keys without a caption give `None`.
+fn key_name(key: Key) -> Option<(&'static str, bool)> {
+    let name = match key {
+        Key::Return | Key::KpReturn => ("Enter", true),
+        Key::Escape => ("Esc", true),
+        Key::Tab => ("Tab", true),
+        Key::Backspace => ("Backspace", true),
+        Key::Delete | Key::KpDelete => ("Delete", true),
+        Key::Insert => ("Insert", true),
+        Key::UpArrow => ("Up", true),
+        Key::DownArrow => ("Down", true),
+        Key::LeftArrow => ("Left", true),
+        Key::RightArrow => ("Right", true),
+        Key::Home => ("Home", true),
+        Key::End => ("End", true),
+        Key::PageUp => ("PageUp", true),
+        Key::PageDown => ("PageDown", true),
+        Key::PrintScreen => ("PrintScreen", true),
+        Key::F1 => ("F1", true),
+        Key::F2 => ("F2", true),
+        Key::F3 => ("F3", true),
+        Key::F4 => ("F4", true),
+        Key::F5 => ("F5", true),
+        Key::F6 => ("F6", true),
+        Key::F7 => ("F7", true),
+        Key::F8 => ("F8", true),
+        Key::F9 => ("F9", true),
+        Key::F10 => ("F10", true),
+        Key::F11 => ("F11", true),
+        Key::F12 => ("F12", true),
+        Key::Space => ("Space", false),
+        Key::KeyA => ("A", false),
+        Key::KeyB => ("B", false),
+        Key::KeyC => ("C", false),
+        Key::KeyD => ("D", false),
+        Key::KeyE => ("E", false),
+        Key::KeyF => ("F", false),
+        Key::KeyG => ("G", false),
+        Key::KeyH => ("H", false),
+        Key::KeyI => ("I", false),
+        Key::KeyJ => ("J", false),
+        Key::KeyK => ("K", false),
+        Key::KeyL => ("L", false),
+        Key::KeyM => ("M", false),
+        Key::KeyN => ("N", false),
+        Key::KeyO => ("O", false),
+        Key::KeyP => ("P", false),
+        Key::KeyQ => ("Q", false),
+        Key::KeyR => ("R", false),
+        Key::KeyS => ("S", false),
+        Key::KeyT => ("T", false),
+        Key::KeyU => ("U", false),
+        Key::KeyV => ("V", false),
+        Key::KeyW => ("W", false),
+        Key::KeyX => ("X", false),
+        Key::KeyY => ("Y", false),
+        Key::KeyZ => ("Z", false),
+        Key::Num0 | Key::Kp0 => ("0", false),
+        Key::Num1 | Key::Kp1 => ("1", false),
+        Key::Num2 | Key::Kp2 => ("2", false),
+        Key::Num3 | Key::Kp3 => ("3", false),
+        Key::Num4 | Key::Kp4 => ("4", false),
+        Key::Num5 | Key::Kp5 => ("5", false),
+        Key::Num6 | Key::Kp6 => ("6", false),
+        Key::Num7 | Key::Kp7 => ("7", false),
+        Key::Num8 | Key::Kp8 => ("8", false),
+        Key::Num9 | Key::Kp9 => ("9", false),
+        Key::Minus | Key::KpMinus => ("-", false),
+        Key::Equal => ("=", false),
+        Key::KpPlus => ("+", false),
+        Key::KpMultiply => ("*", false),
+        Key::KpDivide => ("/", false),
+        Key::BackQuote => ("`", false),
+        Key::LeftBracket => ("[", false),
+        Key::RightBracket => ("]", false),
+        Key::SemiColon => (";", false),
+        Key::Quote => ("'", false),
+        Key::BackSlash | Key::IntlBackslash => ("\\", false),
+        Key::Comma => (",", false),
+        Key::Dot => (".", false),
+        Key::Slash => ("/", false),
+        _ => return None,
+    };
+    Some(name)
+}
@@ -0,0 +1,84 @@
+use std::collections::VecDeque;
+
+/// Mixes system audio into the microphone signal ahead of the Opus encoder.
+///
+/// Both sources are already converted to the encode rate and channel layout.
+/// The microphone drives the clock: each mic chunk takes the same number of
+/// system samples, in order, so the system audio plays back without gaps
+/// inside it. When not enough has arrived for a chunk, it is held back for
+/// the next one rather than packed in early, which delays the system audio
+/// by the jitter of its device; `max_backlog` bounds that delay.
+pub struct Mixer {
+    channels: usize,
+    mic_gain: f32,
+    system_gain: f32,
+    system: VecDeque<f32>,
+    max_backlog: usize,
+}
+
+impl Mixer {
+    /// `max_backlog` bounds the queued system samples, older ones are dropped.
+    pub fn new(channels: usize, mic_gain: f32, system_gain: f32, max_backlog: usize) -> Self {
+        Self {
+            channels: channels.max(1),
+            mic_gain,
+            system_gain,
+            system: VecDeque::with_capacity(max_backlog),
+            max_backlog,
+        }
+    }
+
+    pub fn mix(&mut self, mic: &[f32], system: &[f32]) -> Vec<f32> {
+        self.system.extend(system);
+        if self.system.len() > self.max_backlog {
+            let excess = (self.system.len() - self.max_backlog).next_multiple_of(self.channels);
+            self.system.drain(..excess.min(self.system.len()));
+        }
+
+        let mut mixed: Vec<f32> = mic.iter().map(|s| s * self.mic_gain).collect();
+        if self.system.len() >= mic.len() {
+            for (out, s) in mixed.iter_mut().zip(self.system.drain(..mic.len())) {
+                *out += s * self.system_gain;
+            }
+        }
+        for out in mixed.iter_mut() {
+            *out = out.clamp(-1.0, 1.0);
+        }
+        mixed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ramp that tells the samples apart after mixing.
+    fn ramp(range: std::ops::Range<usize>) -> Vec<f32> {
+        range.map(|i| i as f32 / 64.0).collect()
+    }
+
+    #[test]
+    fn system_audio_waits_for_a_full_chunk() {
+        let mut mixer = Mixer::new(2, 1.0, 1.0, 64);
+        assert_eq!(mixer.mix(&[0.0; 4], &ramp(0..2)), [0.0; 4]);
+        // Nothing was squeezed in, the stream carries on where it stopped.
+        assert_eq!(mixer.mix(&[0.0; 4], &ramp(2..6)), ramp(0..4));
+        assert_eq!(mixer.mix(&[0.0; 4], &ramp(6..8)), ramp(4..8));
+        assert_eq!(mixer.mix(&[0.0; 4], &[]), [0.0; 4]);
+    }
+
+    #[test]
+    fn an_overlong_backlog_loses_its_oldest_frames() {
+        let mut mixer = Mixer::new(2, 1.0, 1.0, 7);
+        // 12 samples over a cap of 7 drop 6, whole frames only.
+        assert_eq!(mixer.mix(&[0.0; 2], &ramp(0..12)), ramp(6..8));
+        assert_eq!(mixer.mix(&[0.0; 4], &[]), ramp(8..12));
+    }
+
+    #[test]
+    fn gains_apply_before_clipping() {
+        let mut mixer = Mixer::new(1, 0.5, 2.0, 64);
+        assert_eq!(mixer.mix(&[0.5, -0.5], &[0.125, -0.125]), [0.5, -0.5]);
+        assert_eq!(mixer.mix(&[1.0, -1.0], &[0.5, -0.5]), [1.0, -1.0]);
+    }
+}
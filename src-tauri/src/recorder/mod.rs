@@ -1,12 +1,38 @@
+mod accumulator;
+#[cfg(target_os = "linux")]
+mod camera;
+mod clock;
+mod compose;
 mod convert;
+mod cursor;
+mod error;
+mod events;
+mod highlight;
+mod i420;
+mod input;
+mod mixer;
+mod output;
+mod paint;
+mod privacy;
+mod queue;
+mod reorder;
+#[cfg(feature = "hq_resample")]
+mod resample;
+mod scale;
+mod scheduler;
+mod source;
+mod watermark;
 
-use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::BufWriter;
-use std::io::Cursor;
-use std::io::Write;
+pub use error::{RecorderError, RecorderResult};
+pub use events::{RecordingFailed, RecordingPaused, RecordingStarted, RecordingStopped};
+pub use privacy::set_privacy_masks;
+pub use reorder::ReorderStats;
+pub use scheduler::FrameStats;
+pub use source::{list_cameras, list_monitors, list_windows, select_source, CaptureSource};
+
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use chrono::prelude::*;
@@ -14,22 +40,17 @@ use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
     BufferSize, Device, Host, InputCallbackInfo, StreamConfig, SupportedStreamConfig,
 };
-use crossbeam::channel::{unbounded, Receiver, Sender};
+use crossbeam::channel::{bounded, Receiver, RecvTimeoutError, SendTimeoutError, Sender};
 use num_cpus;
 use opus::{Application::*, Channels, Channels::*, Encoder};
 use ringbuf::{traits::*, HeapRb, SharedRb};
 use webm::mux;
 use webm::mux::Track;
-use xcap::Monitor;
 
 use anyhow::{anyhow, bail, Context};
 pub type ResultType<F, E = anyhow::Error> = anyhow::Result<F, E>;
 
-#[derive(Debug, serde::Deserialize)]
-enum Codec {
-    Vp8,
-    Vp9,
-}
+use crate::config::{get_config, Codec, ColorRange, OverloadPolicy, RecordingProfile, Rect};
 
 // Add this struct to store audio configuration
 #[derive(Debug, Clone)] // Add Clone derive
@@ -40,45 +61,139 @@ pub struct AudioConfig {
     pub encode_channel: Channels,
 }
 
+type AudioConsumer =
+    ringbuf::wrap::caching::Caching<Arc<SharedRb<ringbuf::storage::Heap<f32>>>, false, true>;
+
 struct AVPacket {
     audio_data: Vec<f32>,
+    /// Loopback samples, mixed into `audio_data` by the consumer.
+    system_audio: Vec<f32>,
     video_data: Vec<u8>,
+    width: u32,
+    height: u32,
+    /// Webcam frame composited onto the video by the converter.
+    camera: Option<Arc<source::Frame>>,
+    /// Areas the converter hides before anything else is drawn.
+    masks: Option<Vec<privacy::MaskRegion>>,
+    /// Mouse pointer drawn onto the video by the converter.
+    pointer: Option<cursor::Pointer>,
+    /// Click ripples and key caption drawn onto the video by the converter.
+    input: Option<highlight::Effects>,
+    /// When the frame was captured, for the watermark.
+    wall_clock: DateTime<Local>,
     ms: u64,
     seq: u64, // Add sequence number
+    /// Bytes reserved in the `VideoBudget`, released once encoded.
+    charge: usize,
+}
+
+impl AVPacket {
+    /// Drops the picture and everything drawn onto it, keeping the audio.
+    fn drop_picture(&mut self) {
+        self.video_data = Vec::new();
+        self.camera = None;
+        self.masks = None;
+        self.pointer = None;
+        self.input = None;
+    }
 }
 
 lazy_static::lazy_static! {
     static ref HOST: Host = cpal::default_host();
-    static ref INPUT_BUFFER: Arc<Mutex<std::collections::VecDeque<f32>>> = Default::default();
-    static ref VOICE_CALL_INPUT_DEVICE: Arc::<Mutex::<Option<String>>> = Default::default();
 }
+
+// Name fragments of the output monitor/loopback inputs on each platform.
+const LOOPBACK_DEVICE_HINTS: [&str; 5] = [
+    "monitor",
+    "loopback",
+    "stereo mix",
+    "blackhole",
+    "soundflower",
+];
 pub static RECORDING: AtomicBool = AtomicBool::new(false);
+pub static PAUSED: AtomicBool = AtomicBool::new(false);
 
-fn setup_audio() -> ResultType<(
-    impl StreamTrait,
-    AudioConfig,
-    ringbuf::wrap::caching::Caching<
-        std::sync::Arc<SharedRb<ringbuf::storage::Heap<f32>>>,
-        false,
-        true,
-    >,
-)> {
-    use cpal::SampleFormat::*;
+// Audio is padded or trimmed once it drifts this far from the video clock.
+const AV_SYNC_THRESHOLD: Duration = Duration::from_millis(100);
+
+// Rates the Opus encoder accepts, devices at any other rate are resampled.
+#[cfg(feature = "hq_resample")]
+const OPUS_SAMPLE_RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioInputInfo {
+    pub name: String,
+    pub is_default: bool,
+    pub configs: Vec<AudioInputConfig>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioInputConfig {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: String,
+}
 
-    let host = cpal::default_host();
-    let device = host
+#[tauri::command]
+#[specta::specta]
+pub fn list_audio_inputs() -> Vec<AudioInputInfo> {
+    let default_name = HOST
         .default_input_device()
-        .expect("no input device available");
+        .and_then(|d| d.name().ok())
+        .unwrap_or_default();
+    let devices = match HOST.input_devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            eprintln!("Failed to get audio devices: {}", e);
+            return vec![];
+        }
+    };
+    devices
+        .filter_map(|device| {
+            let name = device.name().ok()?;
+            let configs = device
+                .supported_input_configs()
+                .map(|configs| {
+                    configs
+                        .map(|c| AudioInputConfig {
+                            channels: c.channels(),
+                            min_sample_rate: c.min_sample_rate().0,
+                            max_sample_rate: c.max_sample_rate().0,
+                            sample_format: c.sample_format().to_string(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            Some(AudioInputInfo {
+                is_default: name == default_name,
+                name,
+                configs,
+            })
+        })
+        .collect()
+}
 
-    let config = device
-        .default_input_config()
-        .expect("no default input config");
+fn setup_audio(
+    audio_input: Option<&str>,
+) -> ResultType<(impl StreamTrait, AudioConfig, AudioConsumer)> {
+    let (device, config) = get_device(audio_input)?;
 
     let sample_rate_0 = config.sample_rate().0;
     let device_channel = config.channels();
 
     let encode_channel = if device_channel > 1 { Stereo } else { Mono };
     // Sample rate must be one of 8000, 12000, 16000, 24000, or 48000.
+    #[cfg(feature = "hq_resample")]
+    let sample_rate = if OPUS_SAMPLE_RATES.contains(&sample_rate_0) {
+        sample_rate_0
+    } else {
+        // Opus works at 48 kHz internally, so nothing is lost on the way.
+        48000
+    };
+    #[cfg(not(feature = "hq_resample"))]
     let sample_rate = if sample_rate_0 < 12000 {
         8000
     } else if sample_rate_0 < 16000 {
@@ -90,23 +205,7 @@ fn setup_audio() -> ResultType<(
     } else {
         48000
     };
-    // Create a ring buffer with capacity for 1 second of audio
-    let rb = HeapRb::<f32>::new(sample_rate as usize * device_channel as usize);
-    let (mut producer, consumer) = rb.split();
-
-    let stream = match config.sample_format() {
-        I8 => build_input_stream::<i8>(producer, device, &config, encode_channel)?,
-        I16 => build_input_stream::<i16>(producer, device, &config, encode_channel)?,
-        I32 => build_input_stream::<i32>(producer, device, &config, encode_channel)?,
-        I64 => build_input_stream::<i64>(producer, device, &config, encode_channel)?,
-        U8 => build_input_stream::<u8>(producer, device, &config, encode_channel)?,
-        U16 => build_input_stream::<u16>(producer, device, &config, encode_channel)?,
-        U32 => build_input_stream::<u32>(producer, device, &config, encode_channel)?,
-        U64 => build_input_stream::<u64>(producer, device, &config, encode_channel)?,
-        F32 => build_input_stream::<f32>(producer, device, &config, encode_channel)?,
-        F64 => build_input_stream::<f64>(producer, device, &config, encode_channel)?,
-        f => bail!("unsupported audio format: {:?}", f),
-    };
+    let (stream, consumer) = open_input_stream(device, &config, sample_rate, encode_channel)?;
 
     Ok((
         stream,
@@ -120,6 +219,74 @@ fn setup_audio() -> ResultType<(
     ))
 }
 
+/// Opens the output monitor/loopback source, converted to the mic's encode format.
+fn setup_system_audio(
+    device_name: Option<&str>,
+    mic: &AudioConfig,
+) -> ResultType<(impl StreamTrait, AudioConfig, AudioConsumer)> {
+    let device = HOST
+        .input_devices()
+        .with_context(|| "Failed to get audio devices")?
+        .find(|d| {
+            let name = d.name().unwrap_or_default();
+            match device_name {
+                Some(device_name) => name == device_name,
+                None => {
+                    let name = name.to_lowercase();
+                    LOOPBACK_DEVICE_HINTS.iter().any(|hint| name.contains(hint))
+                }
+            }
+        })
+        .with_context(|| "No loopback input device found")?;
+    let config = device
+        .default_input_config()
+        .map_err(|e| anyhow!(e))
+        .with_context(|| "Failed to get loopback input format")?;
+    println!(
+        "system audio from {}",
+        device.name().unwrap_or("".to_owned())
+    );
+
+    let audio_config = AudioConfig {
+        sample_rate_0: config.sample_rate().0,
+        sample_rate: mic.sample_rate,
+        device_channel: config.channels(),
+        encode_channel: mic.encode_channel,
+    };
+    let (stream, consumer) =
+        open_input_stream(device, &config, mic.sample_rate, mic.encode_channel)?;
+    Ok((stream, audio_config, consumer))
+}
+
+fn open_input_stream(
+    device: Device,
+    config: &SupportedStreamConfig,
+    sample_rate: u32,
+    encode_channel: Channels,
+) -> ResultType<(cpal::Stream, AudioConsumer)> {
+    use cpal::SampleFormat::*;
+
+    // Create a ring buffer with capacity for 1 second of audio
+    let rb = HeapRb::<f32>::new(sample_rate as usize * config.channels() as usize);
+    let (producer, consumer) = rb.split();
+
+    let stream = match config.sample_format() {
+        I8 => build_input_stream::<i8>(producer, device, config, encode_channel)?,
+        I16 => build_input_stream::<i16>(producer, device, config, encode_channel)?,
+        I32 => build_input_stream::<i32>(producer, device, config, encode_channel)?,
+        I64 => build_input_stream::<i64>(producer, device, config, encode_channel)?,
+        U8 => build_input_stream::<u8>(producer, device, config, encode_channel)?,
+        U16 => build_input_stream::<u16>(producer, device, config, encode_channel)?,
+        U32 => build_input_stream::<u32>(producer, device, config, encode_channel)?,
+        U64 => build_input_stream::<u64>(producer, device, config, encode_channel)?,
+        F32 => build_input_stream::<f32>(producer, device, config, encode_channel)?,
+        F64 => build_input_stream::<f64>(producer, device, config, encode_channel)?,
+        f => bail!("unsupported audio format: {:?}", f),
+    };
+
+    Ok((stream, consumer))
+}
+
 fn build_input_stream<T>(
     producer: ringbuf::wrap::caching::Caching<
         Arc<SharedRb<ringbuf::storage::Heap<f32>>>,
@@ -151,7 +318,8 @@ where
     let frame_size = sample_rate_0 as usize / 100; // 10 ms
     let encode_len = frame_size * encode_channel as usize;
     let rechannel_len = encode_len * device_channel as usize / encode_channel as usize;
-    INPUT_BUFFER.lock().unwrap().clear();
+    // Each stream keeps its own partial frame, the mic and loopback run side by side.
+    let mut pending = std::collections::VecDeque::new();
     let timeout = None;
     let stream_config = StreamConfig {
         channels: device_channel,
@@ -162,11 +330,13 @@ where
     let stream = device.build_input_stream(
         &stream_config,
         move |data: &[T], _: &InputCallbackInfo| {
-            let buffer: Vec<f32> = data.iter().map(|s| T::to_sample(*s)).collect();
-            let mut lock = INPUT_BUFFER.lock().unwrap();
-            lock.extend(buffer);
-            while lock.len() >= rechannel_len {
-                let frame: Vec<f32> = lock.drain(0..rechannel_len).collect();
+            // Samples captured while paused are dropped, not delayed.
+            if PAUSED.load(Ordering::Acquire) {
+                return;
+            }
+            pending.extend(data.iter().map(|s| T::to_sample(*s)));
+            while pending.len() >= rechannel_len {
+                let frame: Vec<f32> = pending.drain(0..rechannel_len).collect();
                 producer.push_slice(&frame);
             }
         },
@@ -178,101 +348,365 @@ where
 }
 
 fn producer(
-    monitor: Monitor,
-    audio_consumer: ringbuf::wrap::caching::Caching<
-        std::sync::Arc<SharedRb<ringbuf::storage::Heap<f32>>>,
-        false,
-        true,
-    >,
+    mut source: CaptureSource,
+    audio_consumer: AudioConsumer,
+    system_audio_consumer: Option<AudioConsumer>,
     audio_config: AudioConfig,
+    mut camera: Option<source::CameraFeed>,
+    masks: privacy::MaskTracker,
+    show_cursor: bool,
+    mut input: Option<highlight::InputTracker>,
+    fps: u32,
+    policy: OverloadPolicy,
+    budget: Arc<queue::VideoBudget>,
     sender: Sender<AVPacket>,
-) {
-    let fps = 60.0;
-    let frame_duration = 1000.0 / fps;
+    receiver: Receiver<AVPacket>,
+) -> RecorderResult<FrameStats> {
+    let mut scheduler = scheduler::FrameScheduler::new(fps);
     let start = Instant::now();
+    let mut paused = Duration::ZERO;
     let mut seq = 0; // Initialize sequence counter
+    let mut next_ms = 0;
 
-    // Calculate how many audio samples we want per frame
+    // Roughly how many audio samples arrive per frame
     let samples_per_frame =
-        ((audio_config.sample_rate as f32 / fps) as usize) * audio_config.encode_channel as usize;
+        (audio_config.sample_rate / fps.max(1)) as usize * audio_config.encode_channel as usize;
 
     let mut audio_buffer: Vec<f32> = Vec::with_capacity(samples_per_frame);
 
     let mut consumer = audio_consumer;
+    let mut system_consumer = system_audio_consumer;
     while RECORDING.load(Ordering::Acquire) {
         // println!("produce {}", seq);
 
-        let video_data = monitor.capture_bytes().unwrap();
+        if PAUSED.load(Ordering::Acquire) {
+            let paused_at = Instant::now();
+            while PAUSED.load(Ordering::Acquire) && RECORDING.load(Ordering::Acquire) {
+                consumer.clear();
+                if let Some(c) = system_consumer.as_mut() {
+                    c.clear();
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            consumer.clear();
+            if let Some(c) = system_consumer.as_mut() {
+                c.clear();
+            }
+            // Timestamps skip the gap, so playback has no frozen section.
+            paused += paused_at.elapsed();
+            scheduler.reset();
+            continue;
+        }
+
+        scheduler.wait();
+        // Returning drops the sender, which winds down the converters.
+        let frame = source
+            .capture()
+            .map_err(|e| RecorderError::Capture(format!("{:#}", e)))?;
+        // Video is stamped with its capture time, or when a camera took it.
+        let taken = frame.timestamp.unwrap_or_else(Instant::now);
+        let time: Duration = taken
+            .saturating_duration_since(start)
+            .saturating_sub(paused);
+        // A buffered frame can't go back past the one before it.
+        let ms: u64 = (time.as_millis() as u64).max(next_ms);
+        next_ms = ms + 1;
+        scheduler.captured();
+        let wall_clock =
+            Local::now() - chrono::TimeDelta::from_std(taken.elapsed()).unwrap_or_default();
+        let hidden = masks.regions(&source);
+        let pointer = show_cursor.then(|| source.pointer()).flatten();
+        let effects = input
+            .as_mut()
+            .and_then(|input| input.effects(taken, &source));
+
+        // The camera frame taken closest before the screen frame.
+        let camera_frame = match camera.as_ref().map(|camera| camera.frame_at(taken)) {
+            Some(Ok(frame)) => Some(frame),
+            Some(Err(e)) => {
+                // Losing the webcam shouldn't end the screen recording.
+                eprintln!("record without the webcam overlay: {:#}", e);
+                camera = None;
+                None
+            }
+            None => None,
+        };
 
-        // Collect audio samples
+        // Collect audio samples, whatever arrived since the last frame. Audio
+        // is not waited on so it can't hold back the next deadline.
         audio_buffer.clear();
-        while audio_buffer.len() < samples_per_frame as usize {
-            audio_buffer.extend(consumer.pop_iter());
-            // let received = consumer.pop_iter();
-            // if received.len() > 0 {
-            //     audio_buffer.extend(received);
-            // } else {
-            //     audio_buffer.push(0.0)
-            // }
-        }
+        audio_buffer.extend(consumer.pop_iter());
 
-        let time: Duration = Instant::now() - start;
-        let ms: u64 = time.as_secs() * 1000 + time.subsec_millis() as u64;
+        // System audio is not waited on, the mixer lines it up with the mic.
+        let system_audio: Vec<f32> = match system_consumer.as_mut() {
+            Some(c) => c.pop_iter().collect(),
+            None => Vec::new(),
+        };
 
-        sender
-            .send(AVPacket {
-                video_data,
-                audio_data: audio_buffer.clone(),
-                ms,
-                seq,
-            })
-            .unwrap();
+        let mut packet = AVPacket {
+            video_data: frame.data,
+            width: frame.width,
+            height: frame.height,
+            camera: camera_frame,
+            masks: hidden,
+            pointer,
+            input: effects,
+            wall_clock,
+            audio_data: audio_buffer.clone(),
+            system_audio,
+            ms,
+            seq,
+            charge: 0,
+        };
+        let bytes = packet.video_data.len();
+        if make_room(bytes, policy, &budget, &sender, &receiver) {
+            packet.charge = bytes;
+        } else {
+            // Audio still goes through, only the picture is lost.
+            packet.drop_picture();
+            budget.record_drop();
+        }
+        // Audio-only packets only wait once the converters stop taking any.
+        if !send_packet(&sender, packet) {
+            break;
+        }
 
         seq += 1;
-        std::thread::sleep(std::time::Duration::from_millis(frame_duration as u64));
     }
 
     // Producer explicitly drops sender when done
     drop(sender);
+    let mut stats = scheduler.stats();
+    stats.overloaded = budget.dropped();
+    Ok(stats)
+}
+
+/// Reserves room for a frame of `bytes`, applying the overload policy when the
+/// frame or memory cap is full. Returns false when the new frame's video has
+/// to be dropped.
+fn make_room(
+    bytes: usize,
+    policy: OverloadPolicy,
+    budget: &queue::VideoBudget,
+    sender: &Sender<AVPacket>,
+    receiver: &Receiver<AVPacket>,
+) -> bool {
+    loop {
+        if budget.try_reserve(bytes) {
+            return true;
+        }
+        match policy {
+            OverloadPolicy::Block => {
+                if !RECORDING.load(Ordering::Acquire) {
+                    return false;
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            OverloadPolicy::DropNewest => return false,
+            OverloadPolicy::DropOldest => {
+                // Strip the oldest queued frame that still has its picture.
+                // The queue is taken out and put back in order, so that
+                // frame's audio keeps its place instead of going to the back
+                // and reaching the consumer after its gap was skipped.
+                let queued: Vec<AVPacket> = receiver.try_iter().collect();
+                let mut stripped = false;
+                for mut packet in queued {
+                    if !stripped && !packet.video_data.is_empty() {
+                        packet.drop_picture();
+                        budget.release(packet.charge);
+                        packet.charge = 0;
+                        budget.record_drop();
+                        stripped = true;
+                    }
+                    if sender.send(packet).is_err() {
+                        return false;
+                    }
+                }
+                // What holds the budget is already being converted or encoded.
+                if !stripped {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+/// Queues `packet` for the converters, waiting for room. Gives up when the
+/// recording stops, as a failed encoder leaves nothing to take packets.
+fn send_packet(sender: &Sender<AVPacket>, mut packet: AVPacket) -> bool {
+    loop {
+        match sender.send_timeout(packet, Duration::from_millis(10)) {
+            Ok(()) => return true,
+            Err(SendTimeoutError::Timeout(unsent)) if RECORDING.load(Ordering::Acquire) => {
+                packet = unsent;
+            }
+            Err(_) => return false,
+        }
+    }
+}
+
+fn convert_audio(audio_data: Vec<f32>, audio_config: &AudioConfig) -> Vec<f32> {
+    let mut audio_data = audio_data;
+
+    let sample_rate = audio_config.sample_rate;
+    let sample_rate0 = audio_config.sample_rate_0;
+    let device_channel = audio_config.device_channel;
+    let encode_channel = audio_config.encode_channel as _;
+    // The band-limited resampler keeps state between packets, so it runs on
+    // the ordered stream in the consumer instead.
+    let sample_rate = if cfg!(feature = "hq_resample") {
+        sample_rate0
+    } else {
+        sample_rate
+    };
+    if sample_rate0 != sample_rate {
+        audio_data =
+            convert::audio_resample(&audio_data, sample_rate0, sample_rate, device_channel);
+    }
+    if device_channel != encode_channel {
+        audio_data = convert::audio_rechannel(
+            audio_data,
+            sample_rate,
+            sample_rate,
+            device_channel,
+            encode_channel,
+        )
+    }
+    audio_data
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, specta::Type)]
+pub struct ConverterStats {
+    /// Packets converted by this thread.
+    pub frames: u32,
+    /// Seconds spent converting, waiting on either queue excluded.
+    pub busy: f64,
 }
 
 // Add converter function that will run in parallel
 fn converter(
     audio_config: AudioConfig,
-    width: usize,
-    height: usize,
+    system_audio_config: Option<AudioConfig>,
+    area: Rect,
+    mut scaler: Option<scale::Scaler>,
+    mut compositor: Option<compose::Compositor>,
+    mut watermark: Option<watermark::Overlay>,
+    matrix: i420::Matrix,
+    cores: Arc<queue::SpareCores>,
+    pool: Option<Arc<queue::BufferPool>>,
     receiver: Receiver<AVPacket>,
     sender: Sender<AVPacket>,
-) {
+) -> ConverterStats {
+    let mut stats = ConverterStats::default();
+    let width = area.width as usize;
+    let height = area.height as usize;
+    let (output_width, output_height) = scaler
+        .as_ref()
+        .map_or((area.width, area.height), |s| (s.width(), s.height()));
+    let mut region = Vec::new();
+    let mut scaled = Vec::new();
     let mut yuv = Vec::new();
-    while let Ok(packet) = receiver.recv() {
-        convert::argb_to_i420(width, height, &packet.video_data, &mut yuv);
-
-        let mut audio_data = packet.audio_data;
-
-        let sample_rate = audio_config.sample_rate;
-        let sample_rate0 = audio_config.sample_rate_0;
-        let device_channel = audio_config.device_channel;
-        let encode_channel = audio_config.encode_channel as _;
-        if sample_rate0 != sample_rate {
-            audio_data =
-                convert::audio_resample(&audio_data, sample_rate0, sample_rate, device_channel);
-        }
-        if device_channel != encode_channel {
-            audio_data = convert::audio_rechannel(
-                audio_data,
-                sample_rate,
-                sample_rate,
-                device_channel,
-                encode_channel,
-            )
+    while let Ok(mut packet) = receiver.recv() {
+        let started = Instant::now();
+        let frame = Rect {
+            x: 0,
+            y: 0,
+            width: packet.width,
+            height: packet.height,
+        };
+        if packet.video_data.is_empty() {
+            yuv.clear();
+        } else {
+            let threads = cores.claim();
+            let mut data = &mut packet.video_data;
+            if area != frame {
+                convert::crop_argb(
+                    data,
+                    packet.width as usize,
+                    packet.height as usize,
+                    area.x as usize,
+                    area.y as usize,
+                    width,
+                    height,
+                    &mut region,
+                );
+                data = &mut region;
+            }
+            // Masked areas are gone before the frame is drawn on, scaled or
+            // encoded.
+            if let Some(masks) = &packet.masks {
+                privacy::apply(masks, data, width, height, area.x, area.y);
+            }
+            // Clicks and the pointer are in captured pixels, so they go on
+            // before scaling.
+            if let Some(effects) = &packet.input {
+                let mut canvas = paint::Canvas::new(data, width, height);
+                highlight::draw_ripples(&effects.ripples, &mut canvas, area.x, area.y);
+            }
+            if let Some(pointer) = &packet.pointer {
+                cursor::draw(pointer, data, width, height, area.x, area.y);
+            }
+            // Scaling comes after cropping, so the filter only sees the captured area.
+            if let Some(scaler) = scaler.as_mut() {
+                scaler.scale(data, threads, &mut scaled);
+                data = &mut scaled;
+            }
+            // The webcam goes on last, at its final size.
+            if let (Some(compositor), Some(camera)) = (compositor.as_mut(), &packet.camera) {
+                compositor.compose(camera, data);
+            }
+            if let Some(caption) = packet.input.as_ref().and_then(|e| e.caption.as_ref()) {
+                let mut canvas =
+                    paint::Canvas::new(data, output_width as usize, output_height as usize);
+                highlight::draw_caption(caption, &mut canvas);
+            }
+            if let Some(watermark) = watermark.as_mut() {
+                let mut canvas =
+                    paint::Canvas::new(data, output_width as usize, output_height as usize);
+                watermark.apply(&packet.wall_clock, &mut canvas);
+            }
+            convert::argb_to_i420(
+                output_width as usize,
+                output_height as usize,
+                data,
+                &matrix,
+                threads,
+                &mut yuv,
+            );
+            cores.release();
         }
 
+        let audio_data = convert_audio(packet.audio_data, &audio_config);
+        let system_audio = match &system_audio_config {
+            Some(config) if !packet.system_audio.is_empty() => {
+                convert_audio(packet.system_audio, config)
+            }
+            _ => Vec::new(),
+        };
+
+        // A pooled buffer goes out as is and a spare one takes its place.
+        let video_data = match pool.as_deref() {
+            _ if yuv.is_empty() => Vec::new(),
+            Some(pool) => std::mem::replace(&mut yuv, pool.take()),
+            None => yuv.clone(),
+        };
+        stats.frames += 1;
+        stats.busy += started.elapsed().as_secs_f64();
+
         if let Err(_) = sender.send(AVPacket {
-            video_data: yuv.clone(),
+            video_data,
+            width: output_width,
+            height: output_height,
+            camera: None,
+            masks: None,
+            pointer: None,
+            input: None,
+            wall_clock: packet.wall_clock,
             audio_data: audio_data,
+            system_audio,
             ms: packet.ms,
             seq: packet.seq, // Preserve sequence number
+            charge: packet.charge,
         }) {
             println!("convert fail {}", packet.seq);
             break;
@@ -280,17 +714,68 @@ fn converter(
     }
     // Converter explicitly drops sender when done
     drop(sender);
+    stats
 }
 
-fn consumer(width: u32, height: u32, audio_config: AudioConfig, receiver: Receiver<AVPacket>) {
-    let mut buffer = Vec::new();
-    let cursor = Cursor::new(&mut buffer);
+/// Band-limited resamplers from each device rate to the encode rate.
+///
+/// The filters carry history from one packet to the next, so they run on the
+/// ordered stream in the consumer rather than in the converters.
+#[cfg(feature = "hq_resample")]
+struct AudioResamplers {
+    mic: Option<resample::Resampler>,
+    system: Option<resample::Resampler>,
+}
 
-    let mut webm: mux::Segment<mux::Writer<BufWriter<Cursor<&mut Vec<u8>>>>> =
-        mux::Segment::new(mux::Writer::new(BufWriter::new(cursor)))
-            .expect("Could not initialize the multiplexer.");
+#[cfg(feature = "hq_resample")]
+impl AudioResamplers {
+    fn new(audio_config: &AudioConfig, system_audio_config: Option<&AudioConfig>) -> Self {
+        let resampler = |config: &AudioConfig| {
+            (config.sample_rate_0 != config.sample_rate).then(|| {
+                resample::Resampler::new(
+                    config.sample_rate_0,
+                    config.sample_rate,
+                    config.encode_channel as usize,
+                )
+            })
+        };
+        Self {
+            mic: resampler(audio_config),
+            system: system_audio_config.and_then(resampler),
+        }
+    }
+
+    fn process(&mut self, mut packet: AVPacket) -> AVPacket {
+        if let Some(mic) = self.mic.as_mut() {
+            packet.audio_data = mic.process(&packet.audio_data);
+        }
+        if let Some(system) = self.system.as_mut() {
+            packet.system_audio = system.process(&packet.system_audio);
+        }
+        packet
+    }
+}
 
-    let (vpx_codec, mux_codec) = match Codec::Vp9 {
+fn consumer(
+    width: u32,
+    height: u32,
+    audio_config: AudioConfig,
+    system_audio_config: Option<AudioConfig>,
+    profile: RecordingProfile,
+    mut mixer: mixer::Mixer,
+    budget: Arc<queue::VideoBudget>,
+    pool: Option<Arc<queue::BufferPool>>,
+    path: PathBuf,
+    receiver: Receiver<AVPacket>,
+) -> RecorderResult<(u64, ReorderStats)> {
+    let file = output::StreamingFile::create(&path)
+        .map_err(|e| RecorderError::Output(format!("{}: {}", path.display(), e)))?;
+    let mut webm: mux::Segment<mux::Writer<output::StreamingFile>> =
+        mux::Segment::new(mux::Writer::new(file)).ok_or_else(|| {
+            RecorderError::Output("could not initialize the multiplexer".to_string())
+        })?;
+
+    let (vpx_codec, mux_codec) = match profile.codec() {
         Codec::Vp8 => (vpx_encode::VideoCodecId::VP8, mux::VideoCodecId::VP8),
         Codec::Vp9 => (vpx_encode::VideoCodecId::VP9, mux::VideoCodecId::VP9),
     };
@@ -299,147 +784,401 @@ fn consumer(width: u32, height: u32, audio_config: AudioConfig, receiver: Receiv
         width: width,
         height: height,
         timebase: [1, 1000],
-        bitrate: 5000,
+        bitrate: profile.video_bitrate(),
         codec: vpx_codec,
     })
-    .unwrap();
+    .map_err(|e| RecorderError::Encoder(format!("vpx: {:?}", e)))?;
 
     let mut opus_encoder = Encoder::new(
         audio_config.sample_rate,
         audio_config.encode_channel,
         LowDelay,
     )
-    .unwrap();
-    // Calculate the frame size that Opus expects
-    // Opus supports specific frame sizes: 2.5, 5, 10, 20, 40, or 60 ms
-    let supported_frame_duration = vec![2.5, 5.0, 10.0, 20.0, 40.0, 60.0];
-    let rate = audio_config.sample_rate * audio_config.encode_channel as u32;
-    let default_frame_duration = supported_frame_duration.get(4).unwrap();
-    let default_frame_size = (rate as f64 * *default_frame_duration * 0.001) as u64;
-    let rate = audio_config.sample_rate * audio_config.encode_channel as u32;
-    let mut supported_frame_size = vec![];
-    for duration in supported_frame_duration.iter() {
-        supported_frame_size.push((rate as f64 * duration * 0.001) as u64);
-    }
-
+    .map_err(|e| RecorderError::Encoder(format!("opus: {}", e)))?;
     opus_encoder
-        .set_bitrate(opus::Bitrate::Bits(128000))
-        .unwrap();
+        .set_bitrate(opus::Bitrate::Bits(profile.audio_bitrate()))
+        .map_err(|e| RecorderError::Encoder(format!("opus: {}", e)))?;
 
     let mut vt = webm.add_video_track(width, height, None, mux_codec);
+    // 8 bit 4:2:0. The binding has no way to write MatrixCoefficients, so only
+    // the range is signalled and players pick the matrix from the frame size,
+    // which is the only one `ColorMatrix::for_height` hands out.
+    let full_range = profile.color_range() == ColorRange::Full;
+    if !vt.set_color(8, (true, true), full_range) {
+        eprintln!("fail to set the colour of {}", path.display());
+    }
     let mut at = webm.add_audio_track(
         audio_config.sample_rate as i32,
-        audio_config.device_channel as i32,
+        audio_config.encode_channel as i32,
         None,
         mux::AudioCodecId::Opus,
     );
 
-    let silent_frame: Vec<f32> = vec![0.0; default_frame_size as usize];
-    // Pre-encode a silent frame to use when needed
-    let encoded_silence = opus_encoder
-        .encode_vec_float(&silent_frame, silent_frame.len())
-        .unwrap_or_default();
+    let mut accumulator = accumulator::FrameAccumulator::new(
+        audio_config.sample_rate,
+        audio_config.encode_channel as usize,
+    );
 
-    let mut next_seq = 0; // Track expected sequence number
-    let mut pending_packets: BTreeMap<u64, AVPacket> = BTreeMap::new();
-    while let Ok(packet) = receiver.recv() {
-        // Store out-of-order packets
-        pending_packets.insert(packet.seq, packet);
+    let mut clock = clock::AudioClock::new(
+        audio_config.sample_rate,
+        audio_config.encode_channel as usize,
+        AV_SYNC_THRESHOLD,
+    );
+    #[cfg(feature = "hq_resample")]
+    let mut resamplers = AudioResamplers::new(&audio_config, system_audio_config.as_ref());
+    // The converters already brought system audio to the encode rate.
+    #[cfg(not(feature = "hq_resample"))]
+    let _ = system_audio_config;
 
-        while let Some((&seq, packet)) = pending_packets.first_key_value() {
-            if seq != next_seq {
-                break;
+    let mut last_ms = 0;
+    let gap_timeout = profile.reorder_timeout();
+    let poll = (gap_timeout / 4).max(Duration::from_millis(10));
+    let mut reorder = reorder::ReorderBuffer::new(gap_timeout);
+    // Audio of packets that came after their slot was skipped.
+    let mut late_audio = Vec::new();
+    let mut late_system_audio = Vec::new();
+    let mut failure = None;
+    'recv: loop {
+        // Waking up without a packet still lets a timed out gap be skipped.
+        let open = match receiver.recv_timeout(poll) {
+            Ok(packet) => {
+                if let Err(mut late) = reorder.push(packet.seq, packet) {
+                    // Too late for its picture, but its audio goes out with
+                    // the next packet rather than leaving a hole.
+                    late_audio.append(&mut late.audio_data);
+                    late_system_audio.append(&mut late.system_audio);
+                    budget.release(late.charge);
+                    if let Some(pool) = pool.as_deref() {
+                        pool.give(late.video_data);
+                    }
+                }
+                true
             }
+            Err(RecvTimeoutError::Timeout) => true,
+            Err(RecvTimeoutError::Disconnected) => false,
+        };
 
-            // println!("consume {}", next_seq);
-            let packet = pending_packets.remove(&seq).unwrap();
+        if !open {
+            // Once the converters are gone nothing can fill a gap any more.
+            reorder.close();
+        }
+        while let Some(mut packet) = reorder.pop() {
+            prepend(&mut late_audio, &mut packet.audio_data);
+            prepend(&mut late_system_audio, &mut packet.system_audio);
+            #[cfg(feature = "hq_resample")]
+            let packet = resamplers.process(packet);
 
-            for f in vpx.encode(packet.ms as i64, &packet.video_data).unwrap() {
-                vt.add_frame(f.data, f.pts as u64 * 1_000_000, f.key);
+            last_ms = packet.ms;
+            // Frames shed by the overload policy carry audio only.
+            if !packet.video_data.is_empty() {
+                let frames = match vpx.encode(packet.ms as i64, &packet.video_data) {
+                    Ok(frames) => frames,
+                    Err(e) => {
+                        failure = Some(RecorderError::Encoder(format!("vpx: {:?}", e)));
+                        break 'recv;
+                    }
+                };
+                for f in frames {
+                    vt.add_frame(f.data, f.pts as u64 * 1_000_000, f.key);
+                }
+            }
+            budget.release(packet.charge);
+            if let Some(pool) = pool.as_deref() {
+                pool.give(packet.video_data);
             }
 
             // Encode audio data with opus encoder
             if !packet.audio_data.is_empty() {
-                let data = &packet.audio_data;
-                // For audio frames, we can check if the sequence number is 0 (first frame)
-                // or if we're at a regular keyframe interval (e.g. every 60 frames for 1 second at 60fps)
-                let is_keyframe = seq == 0 || seq % 60 == 0;
-
-                if let Some(support) = supported_frame_size
-                    .iter()
-                    .find(|x| **x == data.len() as u64)
-                {
-                    match opus_encoder.encode_vec_float(data, data.len() * 6) {
-                        Ok(data) => {
-                            at.add_frame(&data, packet.ms * 1_000_000, is_keyframe);
-                            println!("success {}, {}", next_seq, packet.audio_data.len());
-                        }
-                        Err(e) => {
-                            at.add_frame(&encoded_silence, packet.ms * 1_000_000, false);
-                            eprintln!(
-                                "Frame {}, data: {}: Audio encoding error: {:?}",
-                                next_seq,
-                                data.len(),
-                                e
-                            )
-                        }
-                    }
-                } else {
-                    at.add_frame(&encoded_silence, packet.ms * 1_000_000, false);
+                let mixed = mixer.mix(&packet.audio_data, &packet.system_audio);
+                let mixed = clock.sync(mixed, packet.ms);
+                clock.advance(mixed.len());
+                accumulator.push(&mixed);
+                while let Some((timestamp, frame)) = accumulator.pop() {
+                    encode_audio_frame(&mut opus_encoder, &mut at, timestamp, &frame);
                 }
             }
+        }
 
-            next_seq += 1;
+        if !open {
+            break;
         }
     }
 
-    let mut frames = vpx.finish().unwrap();
-    while let Some(frame) = frames.next().unwrap() {
-        vt.add_frame(frame.data, frame.pts as u64 * 1_000_000, frame.key);
+    if failure.is_some() {
+        // Stop the rest of the pipeline, what was encoded so far is still saved.
+        stop_record();
+        drop(receiver);
+    }
+
+    // Whatever is left short of a full frame goes out padded with silence.
+    if let Some((timestamp, frame)) = accumulator.flush() {
+        encode_audio_frame(&mut opus_encoder, &mut at, timestamp, &frame);
     }
 
-    let _ = webm.finalize(None);
+    let finished = vpx.finish().and_then(|mut frames| {
+        while let Some(frame) = frames.next()? {
+            vt.add_frame(frame.data, frame.pts as u64 * 1_000_000, frame.key);
+        }
+        Ok(())
+    });
+    if let Err(e) = finished {
+        failure.get_or_insert(RecorderError::Encoder(format!("vpx: {:?}", e)));
+    }
+
+    if clock.resyncs() > 0 {
+        println!("audio resynced {} times", clock.resyncs());
+    }
+
+    // Patches cues, segment size and duration into the already written file.
+    let finalized = match webm.try_finalize(Some(last_ms)) {
+        Ok(writer) => writer
+            .unwrap()
+            .finish()
+            .map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(_) => Err(format!("fail to finalize {}", path.display())),
+    };
+    if let Err(e) = finalized {
+        failure.get_or_insert(RecorderError::Output(e));
+    }
+    println!("finished.");
+    match failure {
+        Some(e) => Err(e),
+        None => Ok((last_ms, reorder.stats())),
+    }
+}
+
+/// Moves `earlier` in front of `samples`, leaving it empty.
+fn prepend(earlier: &mut Vec<f32>, samples: &mut Vec<f32>) {
+    if !earlier.is_empty() {
+        earlier.append(samples);
+        std::mem::swap(earlier, samples);
+    }
+}
+
+fn encode_audio_frame(
+    encoder: &mut Encoder,
+    track: &mut mux::AudioTrack,
+    timestamp: u64,
+    frame: &[f32],
+) {
+    match encoder.encode_vec_float(frame, frame.len() * 6) {
+        // Every Opus packet decodes on its own, so all of them are keyframes.
+        Ok(data) => {
+            track.add_frame(&data, timestamp, true);
+        }
+        Err(e) => eprintln!(
+            "Audio frame at {} ns, data: {}: Audio encoding error: {:?}",
+            timestamp,
+            frame.len(),
+            e
+        ),
+    }
+}
+
+fn output_path(profile: &RecordingProfile) -> RecorderResult<PathBuf> {
+    let dir = PathBuf::from(profile.output_dir());
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| RecorderError::Output(format!("{}: {}", dir.display(), e)))?;
     let now = Utc::now();
     let formatted_time = now.format("%Y-%m-%d %H:%M:%S").to_string();
-    let filename = format!("target/{formatted_time}.webm");
-    let mut file = File::create(filename).expect("fail to create file");
-    file.write_all(&buffer).unwrap();
-    println!("finished.")
+    Ok(dir.join(format!("{formatted_time}.webm")))
 }
 
-pub fn record(monitor: Monitor) {
-    let width = monitor.width() * monitor.scale_factor() as u32;
-    let height = monitor.height() * monitor.scale_factor() as u32;
+/// Records `source` until `stop_record` is called.
+///
+/// Failures are reported through `RecordingFailed` as well as returned; the
+/// part recorded before a worker failed is still finalized.
+pub fn record(source: CaptureSource) -> RecorderResult<()> {
+    match record_session(source) {
+        Ok(stopped) => {
+            events::emit(stopped);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("recording failed: {}", e);
+            stop_record();
+            events::emit(RecordingFailed {
+                reason: e.to_string(),
+            });
+            Err(e)
+        }
+    }
+}
 
-    let (sender, receiver) = unbounded();
-    let (converted_sender, converted_receiver) = unbounded();
+fn record_session(mut source: CaptureSource) -> RecorderResult<RecordingStopped> {
+    let config = get_config().ok();
+    let area = match (&source, &config) {
+        (CaptureSource::Monitor(monitor), Some(config)) => {
+            Some(source::capture_area(monitor, config))
+        }
+        _ => None,
+    };
+    let area = match area {
+        Some(area) => area,
+        None => {
+            // A window is encoded at its initial size; later resizes are cropped
+            // or padded to it in the converters.
+            let frame = source
+                .capture()
+                .map_err(|e| RecorderError::Capture(format!("{:#}", e)))?;
+            Rect {
+                x: 0,
+                y: 0,
+                width: (frame.width & !1).max(2),
+                height: (frame.height & !1).max(2),
+            }
+        }
+    };
+    let profile = config
+        .as_ref()
+        .and_then(|config| config.recording_profile.clone())
+        .unwrap_or_default();
+    let scaler = scale::Scaler::new(area.width, area.height, &profile);
+    let (width, height) = scaler
+        .as_ref()
+        .map_or((area.width, area.height), |s| (s.width(), s.height()));
+    let path = output_path(&profile)?;
+    // About two seconds of frames may wait in each stage.
+    let queue_len = profile.fps() as usize * 2;
+    let (sender, receiver) = bounded(queue_len);
+    let (converted_sender, converted_receiver) = bounded(queue_len);
+    let budget = Arc::new(queue::VideoBudget::new(
+        profile.max_queue_memory(),
+        queue_len * 2,
+    ));
+    let matrix = i420::Matrix::new(i420::ColorMatrix::for_height(height), profile.color_range());
+    // Enough spares for every frame the converted queue can hold.
+    let pool = profile
+        .pool_buffers()
+        .then(|| Arc::new(queue::BufferPool::new(queue_len + num_cpus::get())));
 
     // Setup audio capture
-    let (audio_stream, audio_config, audio_consumer) = setup_audio().unwrap();
+    let audio_input = config.as_ref().and_then(|c| c.audio_input.clone());
+    let (audio_stream, audio_config, audio_consumer) = setup_audio(audio_input.as_deref())
+        .map_err(|e| RecorderError::Audio(format!("{:#}", e)))?;
+    // The producer waits on microphone samples, so the stream has to run first.
+    audio_stream
+        .play()
+        .map_err(|e| RecorderError::Audio(e.to_string()))?;
 
+    let system_audio = config
+        .as_ref()
+        .filter(|c| c.system_audio.unwrap_or(false))
+        .and_then(
+            |c| match setup_system_audio(c.system_audio_device.as_deref(), &audio_config) {
+                Ok(system_audio) => Some(system_audio),
+                Err(e) => {
+                    eprintln!("record without system audio: {:?}", e);
+                    None
+                }
+            },
+        )
+        .filter(|(stream, _, _)| match stream.play() {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("record without system audio: {}", e);
+                false
+            }
+        });
+    let (system_audio_stream, system_audio_config, system_audio_consumer) = match system_audio {
+        Some((stream, config, consumer)) => (Some(stream), Some(config), Some(consumer)),
+        None => (None, None, None),
+    };
+
+    // A camera recorded on its own can't also be opened for the overlay.
+    let overlay = config
+        .as_ref()
+        .and_then(|c| c.webcam_overlay.clone())
+        .filter(|overlay| overlay.enabled() && !source.is_camera());
+    let camera = overlay.as_ref().and_then(|_| {
+        let device = config.as_ref().and_then(|c| c.camera_device.as_deref());
+        match source::CameraFeed::open(device) {
+            Ok(camera) => Some(camera),
+            Err(e) => {
+                eprintln!("record without the webcam overlay: {:#}", e);
+                None
+            }
+        }
+    });
+    let compositor = overlay
+        .filter(|_| camera.is_some())
+        .map(|overlay| compose::Compositor::new(width, height, &overlay));
+    let masks = privacy::MaskTracker::new(
+        config
+            .as_ref()
+            .and_then(|c| c.privacy_masks.clone())
+            .unwrap_or_default(),
+    );
+    let show_cursor = config.as_ref().and_then(|c| c.show_cursor).unwrap_or(true);
+    let input = config
+        .as_ref()
+        .and_then(|c| c.input_overlay.as_ref())
+        .filter(|overlay| overlay.enabled())
+        .map(highlight::InputTracker::new);
+    let watermark = config
+        .as_ref()
+        .and_then(|c| c.watermark.as_ref())
+        .filter(|watermark| watermark.enabled())
+        .map(watermark::Overlay::new);
+
+    PAUSED.store(false, Ordering::Release);
     RECORDING.store(true, Ordering::Release);
     let producer_audio_config = audio_config.clone();
+    let fps = profile.fps();
+    let policy = profile.overload_policy();
+    let producer_budget = budget.clone();
+    let producer_receiver = receiver.clone();
     let producer_thread = std::thread::spawn(move || {
-        producer(monitor, audio_consumer, producer_audio_config, sender);
+        producer(
+            source,
+            audio_consumer,
+            system_audio_consumer,
+            producer_audio_config,
+            camera,
+            masks,
+            show_cursor,
+            input,
+            fps,
+            policy,
+            producer_budget,
+            sender,
+            producer_receiver,
+        )
     });
 
     // Start parallel converter threads
     let num_converters = num_cpus::get().max(2) - 1; // Use available CPU cores minus 1
                                                      // println!("num_converters, {}", num_converters);
+
+    // A converter working alone scales and converts its frame in row bands
+    // on the cores the idle ones leave.
+    let cores = Arc::new(queue::SpareCores::new(num_converters));
     let mut converter_threads = Vec::new();
     for _ in 0..num_converters {
         let receiver = receiver.clone();
         let sender = converted_sender.clone();
 
         let converter_audio_config = audio_config.clone();
+        let converter_system_audio_config = system_audio_config.clone();
+        let converter_scaler = scaler.clone();
+        let converter_compositor = compositor.clone();
+        let converter_watermark = watermark.clone();
+        let converter_cores = cores.clone();
+        let converter_pool = pool.clone();
         let converter_thread = std::thread::spawn(move || {
             converter(
                 converter_audio_config,
-                width as usize,
-                height as usize,
+                converter_system_audio_config,
+                area,
+                converter_scaler,
+                converter_compositor,
+                converter_watermark,
+                matrix,
+                converter_cores,
+                converter_pool,
                 receiver,
                 sender,
-            );
+            )
         });
         converter_threads.push(converter_thread);
     }
@@ -447,37 +1186,131 @@ pub fn record(monitor: Monitor) {
     // Drop the extra sender we created from the clones
     drop(converted_sender);
 
+    events::emit(RecordingStarted {
+        path: path.display().to_string(),
+    });
+
+    let channels = audio_config.encode_channel as usize;
+    let mixer = mixer::Mixer::new(
+        channels,
+        config.as_ref().and_then(|c| c.mic_gain).unwrap_or(1.0),
+        config
+            .as_ref()
+            .and_then(|c| c.system_audio_gain)
+            .unwrap_or(1.0),
+        // 200 ms of system audio may wait for the mic.
+        audio_config.sample_rate as usize * channels / 5,
+    );
     let audio_config = audio_config.clone();
+    let consumer_path = path.clone();
     let consumer_thread = std::thread::spawn(move || {
-        consumer(width, height, audio_config, converted_receiver);
+        let result = consumer(
+            width,
+            height,
+            audio_config,
+            system_audio_config,
+            profile,
+            mixer,
+            budget,
+            pool,
+            consumer_path,
+            converted_receiver,
+        );
+        if result.is_err() {
+            // Without an encoder the producer would capture for nothing.
+            stop_record();
+        }
+        result
     });
 
-    producer_thread.join().unwrap();
-    for thread in converter_threads {
-        thread.join().unwrap();
+    // Every thread is joined before reporting, so the file is finalized even
+    // when one of them failed.
+    let produced = producer_thread
+        .join()
+        .map_err(|_| RecorderError::Worker("producer"))
+        .and_then(|result| result);
+    if produced.is_err() {
+        stop_record();
     }
-    consumer_thread.join().unwrap();
-    audio_stream.play().expect("Failed to start audio stream");
+    let converted: RecorderResult<Vec<ConverterStats>> = converter_threads
+        .into_iter()
+        .map(|thread| {
+            thread
+                .join()
+                .map_err(|_| RecorderError::Worker("converter"))
+        })
+        .collect();
+    let consumed = consumer_thread
+        .join()
+        .map_err(|_| RecorderError::Worker("encoder"))
+        .and_then(|result| result);
+    drop(audio_stream);
+    drop(system_audio_stream);
+
+    let frame_stats = produced?;
+    println!(
+        "frames captured: {}, dropped: {}, late: {}, overloaded: {}",
+        frame_stats.captured, frame_stats.dropped, frame_stats.late, frame_stats.overloaded
+    );
+    let converters = converted?;
+    for (i, stats) in converters.iter().enumerate() {
+        println!(
+            "converter {}: {} frames, {:.1} fps",
+            i,
+            stats.frames,
+            stats.frames as f64 / stats.busy.max(f64::EPSILON)
+        );
+    }
+    let (duration, reorder) = consumed?;
+    println!(
+        "reordered: {}, max pending: {}, skipped: {}, late: {}",
+        reorder.reordered, reorder.max_pending, reorder.skipped, reorder.late
+    );
+    Ok(RecordingStopped {
+        path: path.display().to_string(),
+        duration: duration as f64 / 1000.0,
+        bytes: std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+        frames: frame_stats,
+        converters,
+        reorder,
+    })
 }
 
 #[inline]
 pub fn stop_record() {
     RECORDING.store(false, Ordering::Release);
+    PAUSED.store(false, Ordering::Release);
+}
+
+#[inline]
+pub fn pause_record() {
+    PAUSED.store(true, Ordering::Release);
+    events::emit(RecordingPaused { paused: true });
+}
+
+#[inline]
+pub fn resume_record() {
+    PAUSED.store(false, Ordering::Release);
+    events::emit(RecordingPaused { paused: false });
+}
+
+#[inline]
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Acquire)
 }
 
 const MAX_AUDIO_ZERO_COUNT: u16 = 800;
 static mut AUDIO_ZERO_COUNT: u16 = 0;
 
-fn get_device() -> ResultType<(Device, SupportedStreamConfig)> {
-    let audio_input = get_audio_input();
-    _get_audio_input(&audio_input)
+fn get_device(audio_input: Option<&str>) -> ResultType<(Device, SupportedStreamConfig)> {
+    _get_audio_input(audio_input.unwrap_or_default())
 }
 
 fn _get_audio_input(audio_input: &str) -> ResultType<(Device, SupportedStreamConfig)> {
     let mut device = None;
     if !audio_input.is_empty() {
         for d in HOST
-            .devices()
+            .input_devices()
             .with_context(|| "Failed to get audio devices")?
         {
             if d.name().unwrap_or("".to_owned()) == audio_input {
@@ -485,11 +1318,16 @@ fn _get_audio_input(audio_input: &str) -> ResultType<(Device, SupportedStreamCon
                 break;
             }
         }
+        if device.is_none() {
+            println!("audio input {audio_input} is missing, fall back to the default device");
+        }
     }
-    let device = device.unwrap_or(
-        HOST.default_input_device()
-            .with_context(|| "Failed to get default input device for loopback")?,
-    );
+    let device = match device {
+        Some(device) => device,
+        None => HOST
+            .default_input_device()
+            .with_context(|| "Failed to get default input device")?,
+    };
     // log::info!("Input device: {}", device.name().unwrap_or("".to_owned()));
     let format = device
         .default_input_config()
@@ -499,6 +1337,166 @@ fn _get_audio_input(audio_input: &str) -> ResultType<(Device, SupportedStreamCon
     Ok((device, format))
 }
 
-fn get_audio_input() -> String {
-    VOICE_CALL_INPUT_DEVICE.lock().unwrap().clone().unwrap()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(seq: u64, video: usize, camera: Option<Arc<source::Frame>>) -> AVPacket {
+        AVPacket {
+            audio_data: vec![0.0; 4],
+            system_audio: Vec::new(),
+            video_data: vec![0; video],
+            width: 0,
+            height: 0,
+            camera,
+            masks: Some(Vec::new()),
+            pointer: Some(cursor::Pointer {
+                x: 0,
+                y: 0,
+                scale: 1.0,
+            }),
+            input: Some(highlight::Effects {
+                ripples: Vec::new(),
+                caption: None,
+            }),
+            wall_clock: Local::now(),
+            ms: seq,
+            seq,
+            charge: 0,
+        }
+    }
+
+    /// Queues a frame of `bytes` the way the producer does.
+    fn queue_frame(budget: &queue::VideoBudget, sender: &Sender<AVPacket>, seq: u64, bytes: usize) {
+        let mut packet = packet(seq, bytes, None);
+        if bytes > 0 {
+            assert!(budget.try_reserve(bytes));
+            packet.charge = bytes;
+        }
+        sender.send(packet).unwrap();
+    }
+
+    #[test]
+    fn drop_newest_leaves_the_queue_alone() {
+        let budget = queue::VideoBudget::new(usize::MAX, 1);
+        let (sender, receiver) = bounded(4);
+        queue_frame(&budget, &sender, 0, 16);
+
+        assert!(!make_room(
+            16,
+            OverloadPolicy::DropNewest,
+            &budget,
+            &sender,
+            &receiver
+        ));
+        assert_eq!(receiver.try_recv().unwrap().video_data.len(), 16);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn drop_oldest_strips_the_oldest_picture_in_place() {
+        let budget = queue::VideoBudget::new(usize::MAX, 2);
+        let (sender, receiver) = bounded(4);
+        queue_frame(&budget, &sender, 0, 0);
+        queue_frame(&budget, &sender, 1, 16);
+        queue_frame(&budget, &sender, 2, 16);
+
+        assert!(make_room(
+            16,
+            OverloadPolicy::DropOldest,
+            &budget,
+            &sender,
+            &receiver
+        ));
+        let queued: Vec<(u64, bool)> = receiver
+            .try_iter()
+            .map(|p| (p.seq, p.video_data.is_empty()))
+            .collect();
+        assert_eq!(queued, [(0, true), (1, true), (2, false)]);
+        assert_eq!(budget.dropped(), 1);
+
+        // What holds the budget now is past the queue, nothing to strip.
+        queue_frame(&budget, &sender, 3, 0);
+        assert!(!make_room(
+            16,
+            OverloadPolicy::DropOldest,
+            &budget,
+            &sender,
+            &receiver
+        ));
+        assert_eq!(receiver.try_recv().unwrap().seq, 3);
+    }
+
+    #[test]
+    fn block_waits_for_room_until_stopped() {
+        let budget = Arc::new(queue::VideoBudget::new(usize::MAX, 1));
+        let (sender, receiver) = bounded(4);
+        assert!(budget.try_reserve(16));
+
+        RECORDING.store(true, Ordering::Release);
+        let encoder = {
+            let budget = budget.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+                budget.release(16);
+            })
+        };
+        assert!(make_room(
+            16,
+            OverloadPolicy::Block,
+            &budget,
+            &sender,
+            &receiver
+        ));
+        encoder.join().unwrap();
+
+        RECORDING.store(false, Ordering::Release);
+        assert!(!make_room(
+            16,
+            OverloadPolicy::Block,
+            &budget,
+            &sender,
+            &receiver
+        ));
+        assert_eq!(budget.dropped(), 0);
+    }
+
+    #[test]
+    fn a_full_queue_is_given_up_once_stopped() {
+        let (sender, _receiver) = bounded(1);
+        sender.send(packet(0, 0, None)).unwrap();
+        assert!(!send_packet(&sender, packet(1, 0, None)));
+    }
+
+    #[test]
+    fn stripped_frames_let_go_of_their_overlays() {
+        let camera = Arc::new(source::Frame {
+            data: vec![0; 64],
+            width: 4,
+            height: 4,
+            timestamp: None,
+        });
+        let budget = queue::VideoBudget::new(usize::MAX, 1);
+        let (sender, receiver) = bounded(4);
+        assert!(budget.try_reserve(16));
+        let mut queued = packet(0, 16, Some(camera.clone()));
+        queued.charge = 16;
+        sender.send(queued).unwrap();
+
+        assert!(make_room(
+            16,
+            OverloadPolicy::DropOldest,
+            &budget,
+            &sender,
+            &receiver
+        ));
+        let stripped = receiver.try_recv().unwrap();
+        assert!(stripped.video_data.is_empty());
+        assert!(stripped.camera.is_none());
+        assert!(stripped.masks.is_none());
+        assert!(stripped.pointer.is_none());
+        assert!(stripped.input.is_none());
+        assert_eq!(stripped.audio_data.len(), 4);
+        assert_eq!(Arc::strong_count(&camera), 1);
+    }
 }
@@ -0,0 +1,58 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// How often buffered muxer output is handed to the OS. Anything older than
+// this survives the app crashing, so the partial file stays playable up to
+// that point. It is only synced to the disk at finalize, waiting on a slow
+// disk would stall the encoder.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Seekable file sink for `mux::Writer`.
+///
+/// The muxer writes clusters as frames arrive and seeks back at finalize to
+/// patch sizes, cues and duration, so nothing has to be kept in memory.
+pub struct StreamingFile {
+    inner: BufWriter<File>,
+    last_flush: Instant,
+}
+
+impl StreamingFile {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self {
+            inner: BufWriter::new(file),
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Writes out what is buffered and waits until it is on the disk.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.inner.flush()?;
+        self.inner.get_ref().sync_data()
+    }
+}
+
+impl Write for StreamingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()?;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+impl Seek for StreamingFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        // BufWriter flushes before seeking, so patched headers land in place.
+        self.inner.seek(pos)
+    }
+}
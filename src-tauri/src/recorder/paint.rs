@@ -0,0 +1,176 @@
+use font8x8::{UnicodeFonts, BASIC_FONTS, LATIN_FONTS};
+use image::RgbaImage;
+
+use crate::config::OverlayPosition;
+
+/// Font pixels per glyph side.
+const GLYPH: u32 = 8;
+
+/// A packed ARGB frame that overlays are painted onto. Colours are BGR, the
+/// byte order of the frames.
+pub struct Canvas<'a> {
+    data: &'a mut [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(data: &'a mut [u8], width: usize, height: usize) -> Self {
+        let stride = data.len() / height.max(1);
+        Self {
+            data,
+            width,
+            height,
+            stride,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Mixes `color` into the pixel at (`x`, `y`) by `alpha`, 0 to 1. Points
+    /// off the canvas are ignored.
+    pub fn blend(&mut self, x: i64, y: i64, color: [u8; 3], alpha: f32) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 || alpha <= 0.0 {
+            return;
+        }
+        let o = y as usize * self.stride + 4 * x as usize;
+        let pixel = &mut self.data[o..o + 3];
+        if alpha >= 1.0 {
+            pixel.copy_from_slice(&color);
+            return;
+        }
+        for (d, s) in pixel.iter_mut().zip(color) {
+            *d = (s as f32 * alpha + *d as f32 * (1.0 - alpha)).round() as u8;
+        }
+    }
+
+    /// Blends a `width` x `height` rectangle with its top left at (`x`, `y`).
+    pub fn fill(&mut self, x: i64, y: i64, width: u32, height: u32, color: [u8; 3], alpha: f32) {
+        for row in y.max(0)..(y + height as i64).min(self.height as i64) {
+            for column in x.max(0)..(x + width as i64).min(self.width as i64) {
+                self.blend(column, row, color, alpha);
+            }
+        }
+    }
+
+    /// Draws `text` in the built-in 8x8 font, each font pixel `scale` pixels
+    /// wide, with its top left at (`x`, `y`). Characters the font lacks are
+    /// left blank.
+    pub fn text(&mut self, x: i64, y: i64, text: &str, scale: u32, color: [u8; 3], alpha: f32) {
+        let size = (GLYPH * scale) as i64;
+        for (i, c) in text.chars().enumerate() {
+            let Some(glyph) = glyph(c) else {
+                continue;
+            };
+            let left = x + i as i64 * size;
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in 0..GLYPH as i64 {
+                    if bits & (1 << column) == 0 {
+                        continue;
+                    }
+                    self.fill(
+                        left + column * scale as i64,
+                        y + row as i64 * scale as i64,
+                        scale,
+                        scale,
+                        color,
+                        alpha,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Blends `color` through `mask` with its top left at (`x`, `y`).
+    pub fn mask(&mut self, x: i64, y: i64, mask: &Mask, color: [u8; 3], alpha: f32) {
+        if mask.width == 0 {
+            return;
+        }
+        for (row, coverage) in mask.coverage.chunks_exact(mask.width as usize).enumerate() {
+            for (column, &c) in coverage.iter().enumerate() {
+                if c > 0 {
+                    let alpha = alpha * c as f32 / 255.0;
+                    self.blend(x + column as i64, y + row as i64, color, alpha);
+                }
+            }
+        }
+    }
+
+    /// Blends an RGBA image with its top left at (`x`, `y`), its own alpha
+    /// scaled by `alpha`.
+    pub fn image(&mut self, x: i64, y: i64, image: &RgbaImage, alpha: f32) {
+        for (column, row, pixel) in image.enumerate_pixels() {
+            let [r, g, b, a] = pixel.0;
+            if a > 0 {
+                let alpha = alpha * a as f32 / 255.0;
+                self.blend(x + column as i64, y + row as i64, [b, g, r], alpha);
+            }
+        }
+    }
+}
+
+/// Coverage of a shape, 0 to 255 per pixel, row by row.
+pub struct Mask {
+    pub width: u32,
+    pub height: u32,
+    pub coverage: Vec<u8>,
+}
+
+impl Mask {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            coverage: vec![0; (width * height) as usize],
+        }
+    }
+
+    /// Raises the coverage at (`x`, `y`) to at least `coverage`, ignoring
+    /// points outside.
+    pub fn cover(&mut self, x: i64, y: i64, coverage: u8) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let c = &mut self.coverage[y as usize * self.width as usize + x as usize];
+        *c = (*c).max(coverage);
+    }
+}
+
+/// The built-in font's bitmap for `c`, bit 0 of each row on the left.
+pub fn glyph(c: char) -> Option<[u8; 8]> {
+    BASIC_FONTS.get(c).or_else(|| LATIN_FONTS.get(c))
+}
+
+/// Where a `width` x `height` overlay goes on a canvas of `canvas_width` x
+/// `canvas_height`, `margin` away from the edges it sits at.
+pub fn place(
+    position: OverlayPosition,
+    margin: u32,
+    (width, height): (u32, u32),
+    (canvas_width, canvas_height): (usize, usize),
+) -> (i64, i64) {
+    let margin = margin as i64;
+    let right = canvas_width as i64 - margin - width as i64;
+    let bottom = canvas_height as i64 - margin - height as i64;
+    let center = (canvas_width as i64 - width as i64) / 2;
+    match position {
+        OverlayPosition::TopLeft => (margin, margin),
+        OverlayPosition::Top => (center, margin),
+        OverlayPosition::TopRight => (right, margin),
+        OverlayPosition::BottomLeft => (margin, bottom),
+        OverlayPosition::Bottom => (center, bottom),
+        OverlayPosition::BottomRight => (right, bottom),
+    }
+}
+
+/// Width and height of `text` drawn by `Canvas::text` at `scale`.
+pub fn text_size(text: &str, scale: u32) -> (u32, u32) {
+    (text.chars().count() as u32 * GLYPH * scale, GLYPH * scale)
+}
@@ -0,0 +1,396 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use xcap::Window;
+
+use super::source::{pick_window, CaptureSource};
+use crate::config::{
+    clear_config_cache, get_config_by_app, write_config, Config, MaskStyle, PrivacyMask,
+};
+use crate::APP_HANDLE;
+
+/// How often the tracker thread looks at the windows it follows, and for the
+/// ones that aren't open yet.
+const WINDOW_POLL: Duration = Duration::from_millis(20);
+
+/// Masks changed through `set_privacy_masks`, picked up by the tracker thread.
+static EDITED: Mutex<Option<Vec<PrivacyMask>>> = Mutex::new(None);
+
+/// Replaces the privacy masks in the recording under way, if any, and in the
+/// saved config.
+#[tauri::command]
+#[specta::specta]
+pub fn set_privacy_masks(masks: Vec<PrivacyMask>) {
+    EDITED.lock().replace(masks.clone());
+    if let Some(app) = APP_HANDLE.get() {
+        if let Ok(config) = get_config_by_app(app) {
+            write_config(
+                app,
+                Config {
+                    privacy_masks: Some(masks),
+                    ..config
+                },
+            );
+            clear_config_cache();
+        }
+    }
+}
+
+/// A masked area in captured pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct MaskRegion {
+    pub x: i64,
+    pub y: i64,
+    pub width: i64,
+    pub height: i64,
+    pub style: MaskStyle,
+    pub strength: u32,
+}
+
+/// Works out where the masks are on each frame.
+///
+/// Looking windows up is too slow for the producer, so while any mask follows
+/// a window a thread of its own follows them and publishes where they are;
+/// the producer only maps those rectangles onto the frame. Masking fails
+/// closed: a window keeps its mask at the last place it was seen until it is
+/// known to be closed or minimized, and the masks are in place before the
+/// first frame.
+pub struct MaskTracker {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    /// The masks as of the tracker's last look.
+    masks: Mutex<Vec<Located>>,
+    stop: AtomicBool,
+    /// A tracker thread is running and takes in the edits.
+    tracking: AtomicBool,
+}
+
+/// A mask with the desktop rectangles of its window, if it follows one.
+struct Located {
+    mask: PrivacyMask,
+    /// The window's last two positions, so one being dragged stays covered
+    /// between looks.
+    windows: [Option<Placement>; 2],
+}
+
+/// Where a window is on the desktop, in the units `CaptureSource::locate`
+/// takes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Placement {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+/// A mask as the tracker thread follows it.
+struct Followed {
+    mask: PrivacyMask,
+    window: Option<Window>,
+    last: Option<Placement>,
+    before: Option<Placement>,
+}
+
+impl MaskTracker {
+    /// Finds the masked windows and starts following them.
+    pub fn new(masks: Vec<PrivacyMask>) -> Self {
+        // Edits made before the recording are in the config already.
+        EDITED.lock().take();
+        let tracker = Self {
+            shared: Arc::new(Shared {
+                masks: Mutex::new(Vec::new()),
+                stop: AtomicBool::new(false),
+                tracking: AtomicBool::new(false),
+            }),
+        };
+        tracker.replace(masks);
+        tracker
+    }
+
+    /// Puts `masks` in place once their windows are looked up, and starts
+    /// the tracker thread when there are windows to follow.
+    fn replace(&self, masks: Vec<PrivacyMask>) {
+        let mut followed: Vec<Followed> = masks.into_iter().map(Followed::new).collect();
+        look(&mut followed);
+        *self.shared.masks.lock() = followed.iter().map(Followed::located).collect();
+        if followed.iter().any(Followed::has_window) {
+            self.shared.tracking.store(true, Ordering::Release);
+            let shared = self.shared.clone();
+            std::thread::spawn(move || track(&shared, followed));
+        }
+    }
+
+    /// The masked areas on the frame just captured from `source`, `None`
+    /// when nothing is masked.
+    pub fn regions(&self, source: &CaptureSource) -> Option<Vec<MaskRegion>> {
+        // Without a tracker thread the edits are taken in here. Only one
+        // that adds a window looks the windows up, and only once.
+        if !self.shared.tracking.load(Ordering::Acquire) {
+            if let Some(masks) = take_edit() {
+                self.replace(masks);
+            }
+        }
+
+        let masks = self.shared.masks.lock();
+        let mut regions = Vec::new();
+        for located in masks.iter() {
+            let style = located.mask.style();
+            let strength = located.mask.strength();
+            let mut push = |(x, y, width, height)| {
+                regions.push(MaskRegion {
+                    x,
+                    y,
+                    width,
+                    height,
+                    style,
+                    strength,
+                })
+            };
+            if located.mask.window.is_some() {
+                for window in located.windows.iter().flatten() {
+                    let top_left = source.locate(window.x, window.y);
+                    let bottom_right = source.locate(
+                        window.x + window.width as i32,
+                        window.y + window.height as i32,
+                    );
+                    if let (Some(top_left), Some(bottom_right)) = (top_left, bottom_right) {
+                        push((
+                            top_left.x as i64,
+                            top_left.y as i64,
+                            (bottom_right.x - top_left.x) as i64,
+                            (bottom_right.y - top_left.y) as i64,
+                        ));
+                    }
+                }
+            } else if let Some(region) = located.mask.region {
+                // Like `capture_region`, in the source's own coordinates.
+                let scale = source.scale_factor();
+                push((
+                    (region.x as f32 * scale) as i64,
+                    (region.y as f32 * scale) as i64,
+                    (region.width as f32 * scale).ceil() as i64,
+                    (region.height as f32 * scale).ceil() as i64,
+                ));
+            }
+        }
+        (!regions.is_empty()).then_some(regions)
+    }
+}
+
+impl Drop for MaskTracker {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+    }
+}
+
+/// Follows the masked windows until the tracker is dropped or an edit leaves
+/// no window to follow, taking in edits as they come. Edited masks replace
+/// the old ones only once their windows have been looked up.
+fn track(shared: &Shared, mut followed: Vec<Followed>) {
+    while !shared.stop.load(Ordering::Acquire) {
+        std::thread::sleep(WINDOW_POLL);
+        if let Some(masks) = take_edit() {
+            followed = masks.into_iter().map(Followed::new).collect();
+        }
+        look(&mut followed);
+        *shared.masks.lock() = followed.iter().map(Followed::located).collect();
+        if !followed.iter().any(Followed::has_window) {
+            // Later edits go to the producer, which restarts the thread.
+            shared.tracking.store(false, Ordering::Release);
+            return;
+        }
+    }
+}
+
+fn take_edit() -> Option<Vec<PrivacyMask>> {
+    let masks = EDITED.lock().take()?;
+    println!("privacy masks changed, {} now", masks.len());
+    Some(masks)
+}
+
+/// Updates where the followed windows are. The open windows are listed at
+/// most once, and only when one of them has to be looked for.
+fn look(followed: &mut [Followed]) {
+    let mut open: Option<Option<Vec<Window>>> = None;
+    for f in followed.iter_mut() {
+        let Some(title) = &f.mask.window else {
+            continue;
+        };
+        if f.window.as_mut().is_some_and(|w| w.refresh().is_err()) {
+            f.window = None;
+        }
+        if f.window.is_none() {
+            let open = open.get_or_insert_with(|| Window::all().ok());
+            match open {
+                Some(windows) => f.window = pick_window(windows, title).cloned(),
+                // Nothing is known, so the mask stays where it was.
+                None => continue,
+            }
+        }
+        // Closed or minimized, there is nothing on screen to hide.
+        let now = f
+            .window
+            .as_ref()
+            .filter(|w| !w.is_minimized())
+            .map(|w| Placement {
+                x: w.x(),
+                y: w.y(),
+                width: w.width(),
+                height: w.height(),
+            });
+        if now != f.last {
+            f.before = f.last;
+            f.last = now;
+        } else {
+            f.before = None;
+        }
+    }
+}
+
+impl Followed {
+    fn new(mask: PrivacyMask) -> Self {
+        Self {
+            mask,
+            window: None,
+            last: None,
+            before: None,
+        }
+    }
+
+    fn has_window(&self) -> bool {
+        self.mask.window.is_some()
+    }
+
+    fn located(&self) -> Located {
+        Located {
+            mask: self.mask.clone(),
+            windows: [self.last, self.before],
+        }
+    }
+}
+
+/// Hides `regions` on a packed ARGB frame of `width` x `height` whose top
+/// left is at (`left`, `top`) in captured pixels.
+pub fn apply(
+    regions: &[MaskRegion],
+    frame: &mut [u8],
+    width: usize,
+    height: usize,
+    left: u32,
+    top: u32,
+) {
+    let stride = frame.len() / height.max(1);
+    for region in regions {
+        let clip = |start: i64, length: i64, offset: u32, size: usize| {
+            let start = start - offset as i64;
+            start.clamp(0, size as i64) as usize..(start + length).clamp(0, size as i64) as usize
+        };
+        let xs = clip(region.x, region.width, left, width);
+        let ys = clip(region.y, region.height, top, height);
+        if xs.is_empty() || ys.is_empty() {
+            continue;
+        }
+        let strength = region.strength as usize;
+        match region.style {
+            MaskStyle::Fill => {
+                for y in ys {
+                    for pixel in frame[y * stride..][xs.start * 4..xs.end * 4].chunks_exact_mut(4) {
+                        pixel[..3].fill(0);
+                    }
+                }
+            }
+            MaskStyle::Pixelate => pixelate(frame, stride, xs, ys, strength),
+            MaskStyle::Blur => blur(frame, stride, xs, ys, strength),
+        }
+    }
+}
+
+/// Replaces each `block` x `block` square with its average colour.
+fn pixelate(frame: &mut [u8], stride: usize, xs: Range<usize>, ys: Range<usize>, block: usize) {
+    for top in ys.clone().step_by(block) {
+        let rows = top..(top + block).min(ys.end);
+        for left in xs.clone().step_by(block) {
+            let columns = left * 4..(left + block).min(xs.end) * 4;
+            let mut sum = [0u64; 3];
+            for y in rows.clone() {
+                for pixel in frame[y * stride..][columns.clone()].chunks_exact(4) {
+                    for (s, &c) in sum.iter_mut().zip(pixel) {
+                        *s += c as u64;
+                    }
+                }
+            }
+            let count = (rows.len() * columns.len() / 4) as u64;
+            let average = sum.map(|s| (s / count) as u8);
+            for y in rows.clone() {
+                for pixel in frame[y * stride..][columns.clone()].chunks_exact_mut(4) {
+                    pixel[..3].copy_from_slice(&average);
+                }
+            }
+        }
+    }
+}
+
+/// Three box blurs of `radius` across and down, close to a Gaussian. Only
+/// pixels inside the area are mixed.
+fn blur(frame: &mut [u8], stride: usize, xs: Range<usize>, ys: Range<usize>, radius: usize) {
+    let width = xs.len();
+    let mut pixels: Vec<[u8; 3]> = Vec::with_capacity(width * ys.len());
+    for y in ys.clone() {
+        for pixel in frame[y * stride..][xs.start * 4..xs.end * 4].chunks_exact(4) {
+            pixels.push([pixel[0], pixel[1], pixel[2]]);
+        }
+    }
+
+    let mut line = Vec::new();
+    let mut scratch = Vec::new();
+    for _ in 0..3 {
+        for row in pixels.chunks_exact_mut(width) {
+            box_blur(row, radius, &mut scratch);
+        }
+        for x in 0..width {
+            line.clear();
+            line.extend(pixels[x..].iter().step_by(width));
+            box_blur(&mut line, radius, &mut scratch);
+            for (pixel, &blurred) in pixels[x..].iter_mut().step_by(width).zip(&line) {
+                *pixel = blurred;
+            }
+        }
+    }
+
+    for (y, row) in ys.zip(pixels.chunks_exact(width)) {
+        let out = &mut frame[y * stride..][xs.start * 4..xs.end * 4];
+        for (pixel, blurred) in out.chunks_exact_mut(4).zip(row) {
+            pixel[..3].copy_from_slice(blurred);
+        }
+    }
+}
+
+/// Averages each pixel of `line` with `radius` neighbours on either side,
+/// repeating the end pixels past the ends.
+fn box_blur(line: &mut [[u8; 3]], radius: usize, scratch: &mut Vec<[u8; 3]>) {
+    let n = line.len() as i64;
+    let radius = radius as i64;
+    scratch.clear();
+    scratch.extend_from_slice(line);
+    let at = |i: i64| scratch[i.clamp(0, n - 1) as usize];
+
+    let mut sum = [0u32; 3];
+    for i in -radius..=radius {
+        for (s, c) in sum.iter_mut().zip(at(i)) {
+            *s += c as u32;
+        }
+    }
+    let window = (2 * radius + 1) as u32;
+    for (i, pixel) in line.iter_mut().enumerate() {
+        let i = i as i64;
+        *pixel = sum.map(|s| ((s + window / 2) / window) as u8);
+        for ((s, out), into) in sum.iter_mut().zip(at(i - radius)).zip(at(i + radius + 1)) {
+            *s = *s + into as u32 - out as u32;
+        }
+    }
+}
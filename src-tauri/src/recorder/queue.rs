@@ -0,0 +1,183 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crossbeam::channel::{bounded, Receiver, Sender};
+
+/// Caps the raw video frames and bytes in flight between the producer and
+/// the encoder.
+///
+/// The producer reserves a frame's size before queueing it and the consumer
+/// releases it once the frame is encoded, so the reorder buffer in the
+/// consumer is covered by the cap as well. Packets without video aren't
+/// counted, the bounded queues limit how many of those can pile up.
+pub struct VideoBudget {
+    cap: usize,
+    max_frames: usize,
+    in_flight: AtomicUsize,
+    frames: AtomicUsize,
+    dropped: AtomicU32,
+}
+
+impl VideoBudget {
+    pub fn new(cap: usize, max_frames: usize) -> Self {
+        Self {
+            cap,
+            max_frames: max_frames.max(1),
+            in_flight: AtomicUsize::new(0),
+            frames: AtomicUsize::new(0),
+            dropped: AtomicU32::new(0),
+        }
+    }
+
+    /// Reserves a frame of `bytes`, failing when that would go over either
+    /// cap. Only the producer reserves, so the frame count can't be raised
+    /// between the check and the update.
+    pub fn try_reserve(&self, bytes: usize) -> bool {
+        if self.frames.load(Ordering::Acquire) >= self.max_frames {
+            return false;
+        }
+        let reserved = self
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                // A single frame is always let through, or nothing would move.
+                (in_flight == 0 || in_flight + bytes <= self.cap).then_some(in_flight + bytes)
+            })
+            .is_ok();
+        if reserved {
+            self.frames.fetch_add(1, Ordering::AcqRel);
+        }
+        reserved
+    }
+
+    /// Gives back a frame reserved with `bytes`, nothing when `bytes` is 0.
+    pub fn release(&self, bytes: usize) {
+        if bytes > 0 {
+            self.in_flight.fetch_sub(bytes, Ordering::AcqRel);
+            self.frames.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    pub fn record_drop(&self) {
+        self.dropped.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn dropped(&self) -> u32 {
+        self.dropped.load(Ordering::Acquire)
+    }
+}
+
+/// Shares the cores among the converters busy with a frame, so one working
+/// alone can split its frame into bands while a full pipeline converts a
+/// frame per core.
+pub struct SpareCores {
+    cores: usize,
+    busy: AtomicUsize,
+}
+
+impl SpareCores {
+    pub fn new(cores: usize) -> Self {
+        Self {
+            cores: cores.max(1),
+            busy: AtomicUsize::new(0),
+        }
+    }
+
+    /// Marks a converter busy, returning how many threads its frame may use.
+    /// Every claim is followed by a `release`.
+    pub fn claim(&self) -> usize {
+        let busy = self.busy.fetch_add(1, Ordering::AcqRel) + 1;
+        (self.cores / busy).max(1)
+    }
+
+    pub fn release(&self) {
+        self.busy.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Frame buffers handed back by the consumer for the converters to fill again,
+/// so a steady recording stops allocating once the pool has warmed up.
+pub struct BufferPool {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl BufferPool {
+    /// Keeps at most `capacity` spare buffers, the rest are freed.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = bounded(capacity.max(1));
+        Self { sender, receiver }
+    }
+
+    /// A spare buffer, or a new one when none is left.
+    pub fn take(&self) -> Vec<u8> {
+        self.receiver.try_recv().unwrap_or_default()
+    }
+
+    pub fn give(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() > 0 {
+            buffer.clear();
+            let _ = self.sender.try_send(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_capped_by_count_and_bytes() {
+        let budget = VideoBudget::new(100, 3);
+        assert!(budget.try_reserve(40));
+        assert!(budget.try_reserve(40));
+        // A third 40 would go over 100 bytes, a smaller frame still fits.
+        assert!(!budget.try_reserve(40));
+        assert!(budget.try_reserve(20));
+        // Three frames are in flight.
+        assert!(!budget.try_reserve(1));
+
+        budget.release(40);
+        assert!(budget.try_reserve(40));
+    }
+
+    #[test]
+    fn a_frame_over_the_cap_goes_through_alone() {
+        let budget = VideoBudget::new(10, 4);
+        assert!(budget.try_reserve(100));
+        assert!(!budget.try_reserve(1));
+        budget.release(100);
+        assert!(budget.try_reserve(100));
+    }
+
+    #[test]
+    fn releasing_nothing_keeps_the_reservations() {
+        let budget = VideoBudget::new(100, 1);
+        assert!(budget.try_reserve(10));
+        budget.release(0);
+        assert!(!budget.try_reserve(10));
+        budget.release(10);
+        assert!(budget.try_reserve(10));
+    }
+
+    #[test]
+    fn idle_cores_go_to_the_busy_converters() {
+        let cores = SpareCores::new(4);
+        assert_eq!(cores.claim(), 4);
+        assert_eq!(cores.claim(), 2);
+        assert_eq!(cores.claim(), 1);
+        // More converters than cores still get a thread each.
+        assert_eq!(cores.claim(), 1);
+        assert_eq!(cores.claim(), 1);
+        for _ in 0..5 {
+            cores.release();
+        }
+        assert_eq!(cores.claim(), 4);
+    }
+
+    #[test]
+    fn drops_are_counted() {
+        let budget = VideoBudget::new(100, 1);
+        budget.record_drop();
+        budget.record_drop();
+        assert_eq!(budget.dropped(), 2);
+    }
+}
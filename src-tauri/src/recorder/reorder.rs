@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct ReorderStats {
+    /// Packets that arrived ahead of an earlier one and had to wait.
+    pub reordered: u32,
+    /// Most packets waiting at once.
+    pub max_pending: u32,
+    /// Sequence numbers given up on after the gap timeout.
+    pub skipped: u32,
+    /// Packets that arrived after their slot was skipped. Their video is
+    /// dropped, their audio goes out with the next packet.
+    pub late: u32,
+}
+
+/// Puts packets from the parallel converters back in sequence order.
+///
+/// A missing sequence number holds back everything after it for at most
+/// `gap_timeout`, then the buffer skips to the oldest packet it has. A packet
+/// lost in a converter costs its own frame instead of stalling the recording.
+pub struct ReorderBuffer<T> {
+    gap_timeout: Duration,
+    next_seq: u64,
+    pending: BTreeMap<u64, T>,
+    /// When the current gap opened, while the next packet is missing.
+    gap_since: Option<Instant>,
+    stats: ReorderStats,
+}
+
+impl<T> ReorderBuffer<T> {
+    pub fn new(gap_timeout: Duration) -> Self {
+        Self {
+            gap_timeout,
+            next_seq: 0,
+            pending: BTreeMap::new(),
+            gap_since: None,
+            stats: ReorderStats::default(),
+        }
+    }
+
+    /// Queues `item`, handing it back when its slot was already skipped.
+    pub fn push(&mut self, seq: u64, item: T) -> Result<(), T> {
+        if seq < self.next_seq {
+            self.stats.late += 1;
+            return Err(item);
+        }
+        if seq != self.next_seq {
+            self.stats.reordered += 1;
+        }
+        self.pending.insert(seq, item);
+        self.stats.max_pending = self.stats.max_pending.max(self.pending.len() as u32);
+        Ok(())
+    }
+
+    /// The next packet in sequence, skipping the gap in front of it once it
+    /// has been open for the timeout.
+    pub fn pop(&mut self) -> Option<T> {
+        let &first = self.pending.keys().next()?;
+        if first != self.next_seq {
+            let since = *self.gap_since.get_or_insert_with(Instant::now);
+            if since.elapsed() < self.gap_timeout {
+                return None;
+            }
+            self.skip_to(first);
+        }
+        self.take(first)
+    }
+
+    /// No more packets will arrive, so gaps are skipped right away.
+    pub fn close(&mut self) {
+        self.gap_timeout = Duration::ZERO;
+    }
+
+    pub fn stats(&self) -> ReorderStats {
+        self.stats
+    }
+
+    fn skip_to(&mut self, seq: u64) {
+        println!("skip packets {}..{}", self.next_seq, seq);
+        self.stats.skipped += (seq - self.next_seq) as u32;
+        self.next_seq = seq;
+    }
+
+    fn take(&mut self, seq: u64) -> Option<T> {
+        self.gap_since = None;
+        self.next_seq = seq + 1;
+        self.pending.remove(&seq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(buffer: &mut ReorderBuffer<u64>) -> Vec<u64> {
+        std::iter::from_fn(|| buffer.pop()).collect()
+    }
+
+    #[test]
+    fn packets_ahead_of_a_gap_wait_for_it() {
+        let mut buffer = ReorderBuffer::new(Duration::from_secs(3600));
+        buffer.push(0, 0).unwrap();
+        buffer.push(2, 2).unwrap();
+        buffer.push(3, 3).unwrap();
+        assert_eq!(drain(&mut buffer), [0]);
+
+        buffer.push(1, 1).unwrap();
+        assert_eq!(drain(&mut buffer), [1, 2, 3]);
+        let stats = buffer.stats();
+        assert_eq!(stats.reordered, 2);
+        assert_eq!(stats.max_pending, 3);
+        assert_eq!(stats.skipped, 0);
+    }
+
+    #[test]
+    fn a_gap_is_skipped_after_the_timeout() {
+        let mut buffer = ReorderBuffer::new(Duration::from_millis(20));
+        buffer.push(2, 2).unwrap();
+        assert_eq!(drain(&mut buffer), []);
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(drain(&mut buffer), [2]);
+        assert_eq!(buffer.stats().skipped, 2);
+
+        // The next gap gets the full timeout again.
+        buffer.push(4, 4).unwrap();
+        assert_eq!(drain(&mut buffer), []);
+    }
+
+    #[test]
+    fn late_packets_are_handed_back() {
+        let mut buffer = ReorderBuffer::new(Duration::ZERO);
+        buffer.push(1, 1).unwrap();
+        assert_eq!(drain(&mut buffer), [1]);
+
+        assert_eq!(buffer.push(0, 0), Err(0));
+        assert_eq!(buffer.push(1, 1), Err(1));
+        assert_eq!(buffer.stats().late, 2);
+        buffer.push(2, 2).unwrap();
+        assert_eq!(drain(&mut buffer), [2]);
+    }
+
+    #[test]
+    fn closing_skips_gaps_right_away() {
+        let mut buffer = ReorderBuffer::new(Duration::from_secs(3600));
+        buffer.push(1, 1).unwrap();
+        buffer.push(3, 3).unwrap();
+        assert_eq!(drain(&mut buffer), []);
+
+        buffer.close();
+        assert_eq!(drain(&mut buffer), [1, 3]);
+        assert_eq!(buffer.stats().skipped, 2);
+    }
+}
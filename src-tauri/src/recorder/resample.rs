@@ -0,0 +1,223 @@
+/// Taps on each side of the centre at unity ratio, widened when downsampling.
+const HALF_TAPS: usize = 32;
+/// Phases kept at most, rates with a larger reduced ratio use the nearest one.
+const MAX_PHASES: usize = 1024;
+/// Cutoff as a fraction of the lower Nyquist frequency, the centre of the
+/// transition band.
+const CUTOFF: f64 = 0.9;
+/// Kaiser window shape, about 90 dB of stopband attenuation.
+const BETA: f64 = 9.0;
+
+/// Band-limited resampler for interleaved audio, a Kaiser windowed sinc
+/// evaluated at a fixed set of phases.
+///
+/// The tail of each chunk is kept as history for the next one, so a stream
+/// fed in packets comes out the same as if it had been converted at once.
+pub struct Resampler {
+    channels: usize,
+    /// Input frames per `step` output frames, reduced.
+    step: usize,
+    /// Output frames per `step` input frames, reduced.
+    interp: usize,
+    half: usize,
+    taps: usize,
+    phases: usize,
+    /// `taps` coefficients per phase.
+    filter: Vec<f32>,
+    /// Interleaved input not yet out of the filter's reach.
+    history: Vec<f32>,
+    /// Input frame of the next output frame, relative to `history`.
+    pos: usize,
+    /// Fractional part of that position, in `interp`ths of a frame.
+    frac: usize,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32, channels: usize) -> Self {
+        let from = from.max(1) as usize;
+        let to = to.max(1) as usize;
+        let gcd = gcd(from, to);
+        let (step, interp) = (from / gcd, to / gcd);
+
+        // Downsampling lowers the cutoff and stretches the kernel to match.
+        let scale = (to as f64 / from as f64).min(1.0);
+        let cutoff = 0.5 * CUTOFF * scale;
+        let half = (HALF_TAPS as f64 / scale).ceil() as usize;
+        let taps = 2 * half;
+        let phases = interp.min(MAX_PHASES);
+
+        let mut filter = vec![0.0; phases * taps];
+        for (p, coefficients) in filter.chunks_exact_mut(taps).enumerate() {
+            let offset = p as f64 / phases as f64;
+            let mut sum = 0.0;
+            let kernel: Vec<f64> = (0..taps)
+                .map(|k| {
+                    // Distance of tap `k` from the output position, in input frames.
+                    let x = k as f64 + 1.0 - half as f64 - offset;
+                    let w = 2.0 * cutoff * sinc(2.0 * cutoff * x) * kaiser(x / half as f64);
+                    sum += w;
+                    w
+                })
+                .collect();
+            // Each phase passes DC at unity gain.
+            for (c, w) in coefficients.iter_mut().zip(kernel) {
+                *c = (w / sum) as f32;
+            }
+        }
+
+        let channels = channels.max(1);
+        Self {
+            channels,
+            step,
+            interp,
+            half,
+            taps,
+            phases,
+            filter,
+            // Silence before the stream, so the first outputs line up with it.
+            history: vec![0.0; (half - 1) * channels],
+            pos: half - 1,
+            frac: 0,
+        }
+    }
+
+    /// Resamples the next chunk of the stream. Output trails the input by
+    /// `half` frames, which are produced once later input arrives.
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let channels = self.channels;
+        self.history.extend_from_slice(input);
+        let frames = self.history.len() / channels;
+
+        let mut out =
+            Vec::with_capacity((input.len() / channels * self.interp / self.step + 1) * channels);
+        while self.pos + self.half < frames {
+            let phase = self.frac * self.phases / self.interp;
+            let coefficients = &self.filter[phase * self.taps..(phase + 1) * self.taps];
+            let first = (self.pos + 1 - self.half) * channels;
+            let window = &self.history[first..first + self.taps * channels];
+            for c in 0..channels {
+                let mut acc = 0.0;
+                for (frame, &h) in window.chunks_exact(channels).zip(coefficients) {
+                    acc += frame[c] * h;
+                }
+                out.push(acc);
+            }
+
+            self.frac += self.step;
+            self.pos += self.frac / self.interp;
+            self.frac %= self.interp;
+        }
+
+        // Drop the frames no later output reaches.
+        let consumed = (self.pos + 1).saturating_sub(self.half).min(frames);
+        self.history.drain(..consumed * channels);
+        self.pos -= consumed;
+        out
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * std::f64::consts::PI;
+        x.sin() / x
+    }
+}
+
+/// Kaiser window at `x` in [-1, 1].
+fn kaiser(x: f64) -> f64 {
+    if x.abs() > 1.0 {
+        return 0.0;
+    }
+    bessel_i0(BETA * (1.0 - x * x).sqrt()) / bessel_i0(BETA)
+}
+
+/// Modified Bessel function of the first kind, order zero.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..50 {
+        term *= half / k as f64;
+        sum += term * term;
+        if term * term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f64, rate: u32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * std::f64::consts::PI * freq * i as f64 / rate as f64).sin() as f32)
+            .collect()
+    }
+
+    #[test]
+    fn chunks_come_out_as_one_stream() {
+        // Stereo, with a different tone on each side.
+        let left = sine(440.0, 44100, 10000);
+        let right = sine(3000.0, 44100, 10000);
+        let input: Vec<f32> = left
+            .iter()
+            .zip(&right)
+            .flat_map(|(l, r)| [*l, *r])
+            .collect();
+        let whole = Resampler::new(44100, 48000, 2).process(&input);
+
+        let mut resampler = Resampler::new(44100, 48000, 2);
+        let mut chunked = Vec::new();
+        let mut rest = &input[..];
+        for frames in [1, 7, 441, 1000, 3].into_iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (chunk, tail) = rest.split_at((frames * 2).min(rest.len()));
+            chunked.extend(resampler.process(chunk));
+            rest = tail;
+        }
+        assert_eq!(chunked, whole);
+    }
+
+    #[test]
+    fn upsamples_cd_audio_to_48k() {
+        let input = sine(1000.0, 44100, 44100);
+        let mut resampler = Resampler::new(44100, 48000, 1);
+        let out = resampler.process(&input);
+
+        // A second of output, short of the frames still in the filter.
+        let trailing = resampler.half * 48000 / 44100 + 1;
+        assert!(out.len() <= 48000 && out.len() >= 48000 - trailing);
+
+        // Period from the first and last rising zero crossings past the
+        // filter's start up.
+        let steady = &out[2 * resampler.half..];
+        let crossings: Vec<f64> = steady
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| w[0] < 0.0 && w[1] >= 0.0)
+            .map(|(i, w)| i as f64 + (w[0] / (w[0] - w[1])) as f64)
+            .collect();
+        let cycles = (crossings.len() - 1) as f64;
+        let period = (crossings[crossings.len() - 1] - crossings[0]) / cycles;
+        let freq = 48000.0 / period;
+        assert!((freq - 1000.0).abs() < 0.1, "came out at {freq} Hz");
+
+        // The tone is well inside the passband, so it keeps its level.
+        let peak = steady.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - 1.0).abs() < 0.01, "peak {peak}");
+    }
+}
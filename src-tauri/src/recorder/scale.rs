@@ -0,0 +1,300 @@
+use crate::config::{RecordingProfile, Rect, ScaleFilter};
+
+// Weights are fixed point with this many fractional bits.
+const PRECISION: u32 = 14;
+
+/// Scales packed ARGB frames of one size onto an output canvas.
+///
+/// The picture keeps the aspect ratio of the source and is centred on the
+/// canvas, with black bars when letterboxing. Filter weights are computed
+/// once, so a scaler is built per session and cloned into every converter.
+#[derive(Clone)]
+pub struct Scaler {
+    src_width: usize,
+    src_height: usize,
+    width: usize,
+    height: usize,
+    /// Where the scaled picture goes on the canvas.
+    picture: Rect,
+    horizontal: Taps,
+    vertical: Taps,
+    /// Rows scaled horizontally, waiting for the vertical pass.
+    tmp: Vec<u8>,
+}
+
+impl Scaler {
+    /// A scaler from `src_width` x `src_height` to the profile's output size,
+    /// or `None` when the profile doesn't ask for a different size.
+    pub fn new(src_width: u32, src_height: u32, profile: &RecordingProfile) -> Option<Self> {
+        let (target_width, target_height) = match (profile.output_width, profile.output_height) {
+            (None, None) => return None,
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, scale_dim(src_height, width, src_width)),
+            (None, Some(height)) => (scale_dim(src_width, height, src_height), height),
+        };
+        let target_width = (target_width & !1).max(2);
+        let target_height = (target_height & !1).max(2);
+
+        // Fit inside the target on the tighter side.
+        let (picture_width, picture_height) =
+            if target_width as u64 * src_height as u64 <= target_height as u64 * src_width as u64 {
+                (target_width, scale_dim(src_height, target_width, src_width))
+            } else {
+                (
+                    scale_dim(src_width, target_height, src_height),
+                    target_height,
+                )
+            };
+        let picture_width = (picture_width & !1).clamp(2, target_width);
+        let picture_height = (picture_height & !1).clamp(2, target_height);
+
+        let (width, height) = if profile.letterbox() {
+            (target_width, target_height)
+        } else {
+            (picture_width, picture_height)
+        };
+        if (width, height) == (src_width, src_height)
+            && (picture_width, picture_height) == (width, height)
+        {
+            return None;
+        }
+
+        let picture = Rect {
+            // Even offsets keep the bars out of the picture's chroma.
+            x: ((width - picture_width) / 2) & !1,
+            y: ((height - picture_height) / 2) & !1,
+            width: picture_width,
+            height: picture_height,
+        };
+        Some(Self::build(
+            src_width,
+            src_height,
+            width,
+            height,
+            picture,
+            profile.scale_filter(),
+        ))
+    }
+
+    /// A scaler from `src_width` x `src_height` to exactly `width` x `height`,
+    /// stretching when the aspect ratios differ.
+    pub fn stretch(
+        src_width: u32,
+        src_height: u32,
+        width: u32,
+        height: u32,
+        filter: ScaleFilter,
+    ) -> Self {
+        let picture = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        Self::build(src_width, src_height, width, height, picture, filter)
+    }
+
+    fn build(
+        src_width: u32,
+        src_height: u32,
+        width: u32,
+        height: u32,
+        picture: Rect,
+        filter: ScaleFilter,
+    ) -> Self {
+        Self {
+            src_width: src_width as usize,
+            src_height: src_height as usize,
+            width: width as usize,
+            height: height as usize,
+            picture,
+            horizontal: Taps::new(src_width as usize, picture.width as usize, filter),
+            vertical: Taps::new(src_height as usize, picture.height as usize, filter),
+            tmp: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height as u32
+    }
+
+    /// Scales the source-sized `src` into `dest`, which becomes the canvas.
+    /// Large frames are split into row bands on up to `threads` threads.
+    pub fn scale(&mut self, src: &[u8], threads: usize, dest: &mut Vec<u8>) {
+        let stride = src.len() / self.src_height;
+        let src_len = 4 * self.src_width;
+        let row_len = 4 * self.picture.width as usize;
+        let bands = (self.src_height / 256).clamp(1, 4).min(threads.max(1));
+
+        // Horizontal pass first, so the vertical one runs on narrower rows.
+        self.tmp.clear();
+        self.tmp.resize(self.src_height * row_len, 0);
+        let horizontal = &self.horizontal;
+        let band_rows = self.src_height.div_ceil(bands);
+        let scale_rows = |band: usize, rows: &mut [u8]| {
+            for (i, out) in rows.chunks_exact_mut(row_len).enumerate() {
+                let o = (band * band_rows + i) * stride;
+                horizontal.apply_row(&src[o..o + src_len], out);
+            }
+        };
+        in_bands(&mut self.tmp, band_rows * row_len, scale_rows);
+
+        dest.clear();
+        dest.extend(std::iter::repeat_n([0, 0, 0, 255], self.width * self.height).flatten());
+        let (tmp, vertical) = (&self.tmp, &self.vertical);
+        let canvas_len = 4 * self.width;
+        let left = 4 * self.picture.x as usize;
+        let picture = &mut dest[self.picture.y as usize * canvas_len..];
+        let picture = &mut picture[..self.picture.height as usize * canvas_len];
+        let band_rows = (self.picture.height as usize).div_ceil(bands);
+        let scale_columns = |band: usize, rows: &mut [u8]| {
+            let mut acc = vec![0; row_len];
+            for (i, out) in rows.chunks_exact_mut(canvas_len).enumerate() {
+                let y = band * band_rows + i;
+                vertical.apply_column(y, tmp, &mut acc, &mut out[left..left + row_len]);
+            }
+        };
+        in_bands(picture, band_rows * canvas_len, scale_columns);
+    }
+}
+
+/// Runs `f` on each `band_len` chunk of `data` with its index, on a thread
+/// per chunk when there is more than one.
+fn in_bands(data: &mut [u8], band_len: usize, f: impl Fn(usize, &mut [u8]) + Sync) {
+    if data.len() <= band_len {
+        f(0, data);
+        return;
+    }
+    std::thread::scope(|scope| {
+        for (band, rows) in data.chunks_mut(band_len).enumerate() {
+            let f = &f;
+            scope.spawn(move || f(band, rows));
+        }
+    });
+}
+
+/// `size * num / den`, rounded.
+fn scale_dim(size: u32, num: u32, den: u32) -> u32 {
+    ((size as u64 * num as u64 + den as u64 / 2) / den.max(1) as u64) as u32
+}
+
+/// Source pixels and weights contributing to each output pixel of one axis.
+#[derive(Clone)]
+struct Taps {
+    /// First source pixel read for each output pixel.
+    start: Vec<usize>,
+    /// `size` weights per output pixel, zero padded, summing to about
+    /// `1 << PRECISION`.
+    weights: Vec<i16>,
+    size: usize,
+}
+
+impl Taps {
+    fn new(src: usize, dst: usize, filter: ScaleFilter) -> Self {
+        let (support, kernel): (f64, fn(f64) -> f64) = match filter {
+            ScaleFilter::Bilinear => (1.0, triangle),
+            ScaleFilter::Lanczos => (3.0, lanczos3),
+        };
+        let ratio = src as f64 / dst as f64;
+        // Shrinking widens the filter so every source pixel is covered.
+        let filter_scale = ratio.max(1.0);
+        let support = support * filter_scale;
+        let size = (support.ceil() as usize * 2 + 1).min(src);
+
+        let mut start = Vec::with_capacity(dst);
+        let mut weights = vec![0; dst * size];
+        let mut ws = Vec::with_capacity(size);
+        for x in 0..dst {
+            let center = (x as f64 + 0.5) * ratio;
+            let first = ((center - support + 0.5).max(0.0) as usize).min(src - 1);
+            let last = ((center + support + 0.5) as usize).clamp(first + 1, src);
+
+            ws.clear();
+            ws.extend((first..last).map(|i| kernel((i as f64 - center + 0.5) / filter_scale)));
+            let sum: f64 = ws.iter().sum();
+
+            // Taps near the right edge are shifted left so reads stay in bounds.
+            let base = first.min(src - size);
+            let out = &mut weights[x * size..(x + 1) * size];
+            for (k, w) in ws.iter().enumerate().take(size) {
+                let w = if sum != 0.0 { w / sum } else { 0.0 };
+                out[first - base + k] = (w * (1 << PRECISION) as f64).round() as i16;
+            }
+            start.push(base);
+        }
+        Self {
+            start,
+            weights,
+            size,
+        }
+    }
+
+    fn weights(&self, x: usize) -> &[i16] {
+        &self.weights[x * self.size..(x + 1) * self.size]
+    }
+
+    /// Filters one row of ARGB pixels along its length into `out`.
+    fn apply_row(&self, row: &[u8], out: &mut [u8]) {
+        for (x, pixel) in out.chunks_exact_mut(4).enumerate() {
+            let start = 4 * self.start[x];
+            let taps = &row[start..start + 4 * self.size];
+            let mut acc = [1 << (PRECISION - 1); 4];
+            for (p, &w) in taps.chunks_exact(4).zip(self.weights(x)) {
+                acc[0] += p[0] as i32 * w as i32;
+                acc[1] += p[1] as i32 * w as i32;
+                acc[2] += p[2] as i32 * w as i32;
+                acc[3] += p[3] as i32 * w as i32;
+            }
+            for (p, a) in pixel.iter_mut().zip(acc) {
+                *p = clamp(a >> PRECISION);
+            }
+        }
+    }
+
+    /// Filters output row `y` across the rows of `rows` into `out`.
+    fn apply_column(&self, y: usize, rows: &[u8], acc: &mut [i32], out: &mut [u8]) {
+        let row_len = out.len();
+        acc.fill(1 << (PRECISION - 1));
+        for (k, &w) in self.weights(y).iter().enumerate() {
+            if w == 0 {
+                continue;
+            }
+            let o = (self.start[y] + k) * row_len;
+            for (a, &p) in acc.iter_mut().zip(&rows[o..o + row_len]) {
+                *a += p as i32 * w as i32;
+            }
+        }
+        for (d, a) in out.iter_mut().zip(acc.iter()) {
+            *d = clamp(a >> PRECISION);
+        }
+    }
+}
+
+fn triangle(x: f64) -> f64 {
+    (1.0 - x.abs()).max(0.0)
+}
+
+fn lanczos3(x: f64) -> f64 {
+    if x.abs() < 3.0 {
+        sinc(x) * sinc(x / 3.0)
+    } else {
+        0.0
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * std::f64::consts::PI;
+        x.sin() / x
+    }
+}
+
+fn clamp(x: i32) -> u8 {
+    x.clamp(0, 255) as u8
+}
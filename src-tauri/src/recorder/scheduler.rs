@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, specta::Type)]
+pub struct FrameStats {
+    /// Frames captured and handed to the pipeline.
+    pub captured: u32,
+    /// Deadlines skipped because an earlier capture overran them.
+    pub dropped: u32,
+    /// Captures that finished after the next frame was already due.
+    pub late: u32,
+    /// Frames whose video was dropped by the overload policy.
+    pub overloaded: u32,
+}
+
+/// Paces capture against absolute frame deadlines.
+///
+/// Deadlines are `start + n * interval`, so a slow capture only costs the
+/// frames it overran instead of pushing every later frame back.
+pub struct FrameScheduler {
+    interval: Duration,
+    next: Instant,
+    stats: FrameStats,
+}
+
+impl FrameScheduler {
+    pub fn new(fps: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / fps.max(1),
+            next: Instant::now(),
+            stats: FrameStats::default(),
+        }
+    }
+
+    /// Sleeps until the next deadline, skipping the ones already missed.
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        if now < self.next {
+            std::thread::sleep(self.next - now);
+            return;
+        }
+
+        let missed = ((now - self.next).as_nanos() / self.interval.as_nanos()) as u32;
+        if missed > 0 {
+            self.stats.dropped += missed;
+            self.next += self.interval * missed;
+        }
+    }
+
+    /// Counts a capture for the current deadline and moves to the next one.
+    pub fn captured(&mut self) {
+        self.stats.captured += 1;
+        self.next += self.interval;
+        if Instant::now() > self.next {
+            self.stats.late += 1;
+        }
+    }
+
+    /// Restarts the deadlines from now, e.g. after a pause.
+    pub fn reset(&mut self) {
+        self.next = Instant::now();
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+}
@@ -0,0 +1,396 @@
+use std::cell::Cell;
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use xcap::{Monitor, Window};
+
+use super::cursor::Pointer;
+use super::ResultType;
+use crate::config::{CaptureMode, Config, Rect};
+use crate::windows::{get_current_monitor, get_mouse_location};
+
+#[derive(Serialize, Deserialize, Debug, Clone, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct MonitorInfo {
+    pub id: u32,
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub is_primary: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowInfo {
+    pub id: u32,
+    pub app_name: String,
+    pub title: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct CameraInfo {
+    /// Device node, what `camera_device` is set to.
+    pub path: String,
+    pub name: String,
+    pub formats: Vec<CameraFormat>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, specta::Type)]
+#[serde(rename_all = "camelCase")]
+pub struct CameraFormat {
+    /// `YUYV` or `MJPG`.
+    pub fourcc: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A captured ARGB frame and its size in pixels.
+pub struct Frame {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    /// When the picture was taken, for sources that deliver frames on their
+    /// own clock. `None` stamps the frame when it is captured.
+    pub timestamp: Option<Instant>,
+}
+
+/// What the producer pulls frames from.
+pub enum CaptureSource {
+    Monitor(Monitor),
+    Window {
+        window: Window,
+        /// Repeated while the window is minimized or gone.
+        last_frame: Option<Frame>,
+        /// Scale factor of the monitor the window was on, and where it was.
+        scale: Cell<Option<((i32, i32), f32)>>,
+    },
+    #[cfg(target_os = "linux")]
+    Camera(super::camera::Camera),
+}
+
+impl CaptureSource {
+    pub fn is_camera(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        return matches!(self, CaptureSource::Camera(_));
+        #[cfg(not(target_os = "linux"))]
+        false
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            CaptureSource::Monitor(monitor) => monitor.name().to_string(),
+            CaptureSource::Window { window, .. } => window.title().to_string(),
+            #[cfg(target_os = "linux")]
+            CaptureSource::Camera(camera) => camera.name().to_string(),
+        }
+    }
+
+    /// Captured pixels per unit of the monitor's or window's coordinates.
+    pub fn scale_factor(&self) -> f32 {
+        match self {
+            CaptureSource::Monitor(monitor) => monitor.scale_factor(),
+            CaptureSource::Window { window, scale, .. } => {
+                // Looking up the monitor is a platform query, so it is only
+                // repeated once the window has moved.
+                let at = (window.x(), window.y());
+                match scale.get() {
+                    Some((cached_at, factor)) if cached_at == at => factor,
+                    _ => {
+                        let factor = window.current_monitor().scale_factor();
+                        scale.set(Some((at, factor)));
+                        factor
+                    }
+                }
+            }
+            #[cfg(target_os = "linux")]
+            CaptureSource::Camera(_) => 1.0,
+        }
+    }
+
+    /// The mouse pointer in captured pixels, `None` for a camera or when the
+    /// position can't be read.
+    pub fn pointer(&self) -> Option<Pointer> {
+        let (x, y) = get_mouse_location().ok()?;
+        self.locate(x, y)
+    }
+
+    /// Maps a point on the desktop to captured pixels, `None` for a camera.
+    ///
+    /// Desktop points and the monitor or window origin are in points on
+    /// macOS, which the captured pixels are `scale_factor` times larger than,
+    /// and in pixels elsewhere.
+    pub fn locate(&self, x: i32, y: i32) -> Option<Pointer> {
+        let (origin_x, origin_y) = match self {
+            CaptureSource::Monitor(monitor) => (monitor.x(), monitor.y()),
+            CaptureSource::Window { window, .. } => (window.x(), window.y()),
+            #[cfg(target_os = "linux")]
+            CaptureSource::Camera(_) => return None,
+        };
+        let scale = self.scale_factor();
+        let (x, y) = (x - origin_x, y - origin_y);
+        let (x, y) = if cfg!(target_os = "macos") {
+            ((x as f32 * scale) as i32, (y as f32 * scale) as i32)
+        } else {
+            (x, y)
+        };
+        Some(Pointer { x, y, scale })
+    }
+
+    pub fn capture(&mut self) -> ResultType<Frame> {
+        match self {
+            CaptureSource::Monitor(monitor) => {
+                let data = monitor.capture_bytes()?;
+                Ok(Frame {
+                    data,
+                    width: (monitor.width() as f32 * monitor.scale_factor()) as u32,
+                    height: (monitor.height() as f32 * monitor.scale_factor()) as u32,
+                    timestamp: None,
+                })
+            }
+            CaptureSource::Window {
+                window, last_frame, ..
+            } => {
+                let captured = match window.refresh() {
+                    Ok(_) if window.is_minimized() => None,
+                    Ok(_) => window.capture_image().ok(),
+                    Err(e) => {
+                        debug_print::debug_println!("window {} is gone: {}", window.title(), e);
+                        None
+                    }
+                };
+
+                if let Some(image) = captured {
+                    let (width, height) = image.dimensions();
+                    let mut data = image.into_raw();
+                    // RGBA -> BGRA, the layout `capture_bytes` hands out.
+                    for pixel in data.chunks_exact_mut(4) {
+                        pixel.swap(0, 2);
+                    }
+                    *last_frame = Some(Frame {
+                        data: data.clone(),
+                        width,
+                        height,
+                        timestamp: None,
+                    });
+                    return Ok(Frame {
+                        data,
+                        width,
+                        height,
+                        timestamp: None,
+                    });
+                }
+
+                match last_frame {
+                    Some(frame) => Ok(Frame {
+                        data: frame.data.clone(),
+                        width: frame.width,
+                        height: frame.height,
+                        timestamp: None,
+                    }),
+                    None => anyhow::bail!("window {} can not be captured", window.title()),
+                }
+            }
+            #[cfg(target_os = "linux")]
+            CaptureSource::Camera(camera) => camera.capture(),
+        }
+    }
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn list_windows() -> Vec<WindowInfo> {
+    Window::all()
+        .unwrap_or_default()
+        .iter()
+        .filter(|window| !window.title().is_empty())
+        .map(|window| WindowInfo {
+            id: window.id(),
+            app_name: window.app_name().to_string(),
+            title: window.title().to_string(),
+        })
+        .collect()
+}
+
+/// A webcam composited over the recorded source.
+pub struct CameraFeed {
+    #[cfg(target_os = "linux")]
+    camera: super::camera::Camera,
+    #[cfg(not(target_os = "linux"))]
+    never: std::convert::Infallible,
+}
+
+impl CameraFeed {
+    /// Opens `device`, or the first camera found when `None`.
+    pub fn open(device: Option<&str>) -> ResultType<Self> {
+        #[cfg(target_os = "linux")]
+        return Ok(Self {
+            camera: super::camera::Camera::open(device)?,
+        });
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = device;
+            anyhow::bail!("the webcam overlay needs a V4L2 camera")
+        }
+    }
+
+    /// The camera frame to show with a frame captured at `at`.
+    pub fn frame_at(&self, at: Instant) -> ResultType<Arc<Frame>> {
+        #[cfg(target_os = "linux")]
+        return self.camera.frame_at(at);
+        #[cfg(not(target_os = "linux"))]
+        match self.never {}
+    }
+}
+
+/// Cameras that can be recorded, only V4L2 devices on Linux for now.
+#[tauri::command]
+#[specta::specta]
+pub fn list_cameras() -> Vec<CameraInfo> {
+    #[cfg(target_os = "linux")]
+    return super::camera::list();
+    #[cfg(not(target_os = "linux"))]
+    Vec::new()
+}
+
+#[tauri::command]
+#[specta::specta]
+pub fn list_monitors() -> Vec<MonitorInfo> {
+    Monitor::all()
+        .unwrap_or_default()
+        .iter()
+        .map(|monitor| MonitorInfo {
+            id: monitor.id(),
+            name: monitor.name().to_string(),
+            width: monitor.width(),
+            height: monitor.height(),
+            is_primary: monitor.is_primary(),
+        })
+        .collect()
+}
+
+/// Picks the monitor to record from the saved choice.
+///
+/// Display ids are not stable across reboots on every platform, so a saved
+/// monitor is matched by id and name first and then by name alone. When the
+/// saved monitor is gone the primary monitor is used instead.
+pub fn select_monitor(config: &Config) -> Option<Monitor> {
+    let monitors = Monitor::all().unwrap_or_default();
+
+    if config.monitor_follow_cursor.unwrap_or(false) {
+        if let Some(monitor) = monitor_under_cursor(&monitors) {
+            return Some(monitor);
+        }
+        println!("no monitor under the cursor, fall back to the saved monitor");
+    }
+
+    if let Some(name) = &config.monitor_name {
+        let saved = monitors
+            .iter()
+            .find(|m| Some(m.id()) == config.monitor_id && m.name() == name)
+            .or_else(|| monitors.iter().find(|m| m.name() == name));
+        match saved {
+            Some(monitor) => return Some(monitor.clone()),
+            None => println!("saved monitor {name} is gone, fall back to the primary monitor"),
+        }
+    }
+
+    monitors
+        .iter()
+        .find(|m| m.is_primary())
+        .or(monitors.first())
+        .cloned()
+}
+
+/// Picks what to record: the configured window or camera in those modes,
+/// otherwise a monitor as chosen by `select_monitor`.
+pub fn select_source(config: &Config) -> Option<CaptureSource> {
+    #[cfg(target_os = "linux")]
+    if config.capture_mode == Some(CaptureMode::Camera) {
+        match super::camera::Camera::open(config.camera_device.as_deref()) {
+            Ok(camera) => return Some(CaptureSource::Camera(camera)),
+            Err(e) => println!("camera not available, fall back to a monitor: {:#}", e),
+        }
+    }
+
+    if config.capture_mode == Some(CaptureMode::Window) {
+        match config.capture_window.as_deref().and_then(find_window) {
+            Some(window) => {
+                return Some(CaptureSource::Window {
+                    window,
+                    last_frame: None,
+                    scale: Cell::new(None),
+                })
+            }
+            None => println!("configured window not found, fall back to a monitor"),
+        }
+    }
+
+    select_monitor(config).map(CaptureSource::Monitor)
+}
+
+pub fn find_window(title: &str) -> Option<Window> {
+    let windows = Window::all().unwrap_or_default();
+    pick_window(&windows, title).cloned()
+}
+
+/// The window in `windows` with `title`, or whose title contains it, or of
+/// the app named `title`, in that order.
+pub fn pick_window<'a>(windows: &'a [Window], title: &str) -> Option<&'a Window> {
+    windows
+        .iter()
+        .find(|w| w.title() == title)
+        .or_else(|| windows.iter().find(|w| w.title().contains(title)))
+        .or_else(|| windows.iter().find(|w| w.app_name() == title))
+}
+
+fn monitor_under_cursor(monitors: &[Monitor]) -> Option<Monitor> {
+    if let Ok((x, y)) = get_mouse_location() {
+        if let Ok(monitor) = Monitor::from_point(x, y) {
+            return Some(monitor);
+        }
+    }
+
+    let current = get_current_monitor();
+    let name = current.name()?;
+    monitors.iter().find(|m| m.name() == name).cloned()
+}
+
+/// Resolves the area to record in captured (physical) pixels.
+///
+/// The configured region is given in monitor coordinates, so it is scaled by
+/// the monitor's scale factor, clamped to the screen and rounded down to even
+/// dimensions for I420.
+pub fn capture_area(monitor: &Monitor, config: &Config) -> Rect {
+    let scale = monitor.scale_factor();
+    let screen_width = (monitor.width() as f32 * scale) as u32;
+    let screen_height = (monitor.height() as f32 * scale) as u32;
+    let screen = Rect {
+        x: 0,
+        y: 0,
+        width: screen_width,
+        height: screen_height,
+    };
+
+    let region = match (config.capture_mode, config.capture_region) {
+        (Some(CaptureMode::Region), Some(region)) => region,
+        (Some(CaptureMode::Region), None) => {
+            println!("no capture region configured, record the full monitor");
+            return screen;
+        }
+        _ => return screen,
+    };
+
+    let x = ((region.x as f32 * scale) as u32).min(screen_width.saturating_sub(2));
+    let y = ((region.y as f32 * scale) as u32).min(screen_height.saturating_sub(2));
+    let width = ((region.width as f32 * scale) as u32).min(screen_width - x);
+    let height = ((region.height as f32 * scale) as u32).min(screen_height - y);
+
+    Rect {
+        x,
+        y,
+        width: (width & !1).max(2),
+        height: (height & !1).max(2),
+    }
+}
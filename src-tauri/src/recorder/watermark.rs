@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use ab_glyph::{point, Font, FontArc, PxScale, ScaleFont};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use image::{imageops, RgbaImage};
+
+use super::paint::{self, Canvas, Mask};
+use crate::config::Watermark;
+
+// BGR, like the frames.
+const TEXT: [u8; 3] = [255, 255, 255];
+const SHADOW: [u8; 3] = [0, 0, 0];
+
+/// Burns the watermark text and logo into frames of the output size.
+///
+/// The font and logo are loaded once and shared by the converters. Each
+/// converter keeps the last text it drew, so a timestamp is only laid out
+/// again when it changes.
+#[derive(Clone)]
+pub struct Overlay {
+    settings: Watermark,
+    /// strftime pattern with `{user}` filled in.
+    pattern: Option<String>,
+    font: Option<FontArc>,
+    logo: Option<Arc<RgbaImage>>,
+    /// The text drawn last and its coverage.
+    text: Option<(String, Arc<Mask>)>,
+}
+
+impl Overlay {
+    /// Loads the font and logo, leaving out what can't be loaded.
+    pub fn new(settings: &Watermark) -> Self {
+        let pattern = settings.text.as_ref().map(|text| {
+            // Escaped so a `%` in the login name isn't read as a specifier.
+            let user = user_name().replace('%', "%%");
+            let pattern = text.replace("{user}", &user);
+            if StrftimeItems::new(&pattern).any(|item| matches!(item, Item::Error)) {
+                println!("invalid watermark pattern {text:?}, burn it in as is");
+                return text.replace('%', "%%").replace("{user}", &user);
+            }
+            pattern
+        });
+
+        let font = settings.font.as_ref().and_then(|path| {
+            let font = std::fs::read(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|data| Ok(FontArc::try_from_vec(data)?));
+            match font {
+                Ok(font) => Some(font),
+                Err(e) => {
+                    eprintln!("watermark font {path}: {e}, use the built-in font");
+                    None
+                }
+            }
+        });
+
+        let logo = settings
+            .image
+            .as_ref()
+            .and_then(|path| match image::open(path) {
+                Ok(image) => Some(image.to_rgba8()),
+                Err(e) => {
+                    eprintln!("record without the watermark logo {path}: {e}");
+                    None
+                }
+            })
+            .map(|logo| match settings.image_width {
+                Some(width) if width > 0 && width != logo.width() => {
+                    let height = (logo.height() as u64 * width as u64 / logo.width() as u64) as u32;
+                    imageops::resize(&logo, width, height.max(1), imageops::FilterType::Triangle)
+                }
+                _ => logo,
+            })
+            .map(Arc::new);
+
+        Self {
+            settings: settings.clone(),
+            pattern,
+            font,
+            logo,
+            text: None,
+        }
+    }
+
+    /// Draws the watermark for a frame captured at `wall_clock`.
+    pub fn apply(&mut self, wall_clock: &DateTime<Local>, canvas: &mut Canvas) {
+        let opacity = self.settings.opacity();
+        let margin = self.settings.margin();
+        let size = (canvas.width(), canvas.height());
+
+        if let Some(logo) = &self.logo {
+            let position = self.settings.image_position();
+            let (x, y) = paint::place(position, margin, logo.dimensions(), size);
+            canvas.image(x, y, logo, opacity);
+        }
+
+        let Some(pattern) = &self.pattern else {
+            return;
+        };
+        let text = wall_clock.format(pattern).to_string();
+        let mask = match &self.text {
+            Some((last, mask)) if *last == text => mask.clone(),
+            _ => {
+                let font_size = self.settings.font_size();
+                let mask = Arc::new(match &self.font {
+                    Some(font) => render(font, &text, font_size),
+                    None => render_builtin(&text, font_size),
+                });
+                self.text = Some((text, mask.clone()));
+                mask
+            }
+        };
+        let position = self.settings.text_position();
+        let (x, y) = paint::place(position, margin, (mask.width, mask.height), size);
+        // A drop shadow keeps the text readable on light content.
+        let offset = (self.settings.font_size() / 16).max(1) as i64;
+        canvas.mask(x + offset, y + offset, &mask, SHADOW, 0.6 * opacity);
+        canvas.mask(x, y, &mask, TEXT, opacity);
+    }
+}
+
+/// Lays out `text` in `font`, one line per `line_height` pixels.
+fn render(font: &FontArc, text: &str, line_height: u32) -> Mask {
+    let font = font.as_scaled(PxScale::from(line_height as f32));
+    let lines: Vec<&str> = text.lines().collect();
+    let line_width = |line: &str| {
+        let mut width = 0.0;
+        let mut previous = None;
+        for c in line.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                width += font.kern(previous, id);
+            }
+            width += font.h_advance(id);
+            previous = Some(id);
+        }
+        width
+    };
+    let width = lines
+        .iter()
+        .map(|line| line_width(line))
+        .fold(0.0, f32::max);
+    let mut mask = Mask::new(width.ceil() as u32, line_height * lines.len() as u32);
+
+    for (i, line) in lines.iter().enumerate() {
+        let baseline = i as f32 * line_height as f32 + font.ascent();
+        let mut caret = 0.0;
+        let mut previous = None;
+        for c in line.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += font.kern(previous, id);
+            }
+            let glyph = id.with_scale_and_position(font.scale(), point(caret, baseline));
+            caret += font.h_advance(id);
+            previous = Some(id);
+
+            let Some(outline) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outline.px_bounds();
+            outline.draw(|x, y, coverage| {
+                mask.cover(
+                    bounds.min.x as i64 + x as i64,
+                    bounds.min.y as i64 + y as i64,
+                    (coverage * 255.0).round() as u8,
+                );
+            });
+        }
+    }
+    mask
+}
+
+/// Lays out `text` in the built-in 8x8 font, scaled to fit `line_height`.
+fn render_builtin(text: &str, line_height: u32) -> Mask {
+    let scale = (line_height / 9).max(1);
+    let lines: Vec<&str> = text.lines().collect();
+    let width = lines
+        .iter()
+        .map(|line| paint::text_size(line, scale).0)
+        .max()
+        .unwrap_or(0);
+    let mut mask = Mask::new(width, line_height * lines.len() as u32);
+
+    for (i, line) in lines.iter().enumerate() {
+        let top = (i as u32 * line_height) as i64;
+        for (j, c) in line.chars().enumerate() {
+            let Some(glyph) = paint::glyph(c) else {
+                continue;
+            };
+            let left = (j as u32 * 8 * scale) as i64;
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in 0..8 {
+                    if bits & (1 << column) == 0 {
+                        continue;
+                    }
+                    for dy in 0..scale as i64 {
+                        for dx in 0..scale as i64 {
+                            let x = left + column * scale as i64 + dx;
+                            let y = top + row as i64 * scale as i64 + dy;
+                            mask.cover(x, y, 255);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    mask
+}
+
+/// The login name, from the environment.
+fn user_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default()
+}
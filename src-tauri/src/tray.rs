@@ -6,8 +6,8 @@ use crate::ALWAYS_ON_TOP;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
+use debug_print::debug_println;
 use serde::{Deserialize, Serialize};
-use xcap::Monitor;
 
 use tauri::tray::MouseButton;
 use tauri::{
@@ -15,6 +15,7 @@ use tauri::{
     tray::TrayIconEvent,
     Manager, Runtime,
 };
+use tauri_plugin_notification::NotificationExt;
 use tauri_specta::Event;
 
 #[derive(Serialize, Deserialize, Debug, Clone, specta::Type, tauri_specta::Event)]
@@ -43,6 +44,12 @@ pub fn create_tray<R: Runtime>(app: &tauri::AppHandle<R>) -> tauri::Result<()> {
     let hide_i = PredefinedMenuItem::hide(app, Some("Hide"))?;
     let quit_i = PredefinedMenuItem::quit(app, Some("Quit"))?;
     let pin_i = MenuItem::with_id(app, "pin", "Pin", true, None::<String>)?;
+    let recording = crate::RECORDING.load(Ordering::Acquire);
+    let pause_i = MenuItem::with_id(app, "pause", "Pause", recording, None::<String>)?;
+
+    if recording && recorder::is_paused() {
+        pause_i.set_text("Resume").unwrap();
+    }
 
     if ALWAYS_ON_TOP.load(Ordering::Acquire) {
         pin_i.set_text("Unpin").unwrap();
@@ -58,6 +65,7 @@ pub fn create_tray<R: Runtime>(app: &tauri::AppHandle<R>) -> tauri::Result<()> {
             &show_i,
             &hide_i,
             &pin_i,
+            &pause_i,
             &quit_i,
         ],
     )?;
@@ -93,6 +101,19 @@ pub fn create_tray<R: Runtime>(app: &tauri::AppHandle<R>) -> tauri::Result<()> {
             pinned_from_tray_event.emit(handle).unwrap_or_default();
             create_tray(app).unwrap();
         }
+        "pause" => {
+            if !crate::RECORDING.load(Ordering::Acquire) {
+                return;
+            }
+            if recorder::is_paused() {
+                println!("resume recording.");
+                recorder::resume_record();
+            } else {
+                println!("pause recording.");
+                recorder::pause_record();
+            }
+            create_tray(app).unwrap();
+        }
         "quit" => app.exit(0),
         _ => {}
     });
@@ -133,27 +154,34 @@ pub fn create_tray<R: Runtime>(app: &tauri::AppHandle<R>) -> tauri::Result<()> {
                     recorder::stop_record();
 
                     crate::RECORDING.store(false, Ordering::Release);
+                    create_tray(tray.app_handle()).unwrap();
                     return;
                 }
 
-                let configured = get_config().unwrap().configured.unwrap_or(false);
+                let config = get_config().unwrap();
+                let configured = config.configured.unwrap_or(false);
                 if !configured {
-                    println!("not configured yet");
+                    debug_println!("not configured yet");
                     crate::windows::show_window(false, false, true);
                 } else {
-                    println!("configured");
+                    let Some(source) = recorder::select_source(&config) else {
+                        let error =
+                            recorder::RecorderError::Capture("no monitor to record".to_string());
+                        recording_failed(tray, &error);
+                        return;
+                    };
+                    debug_println!("start recording {}.", source.name());
                     set_recording_icon(tray, true);
-                    if let Some(monitor) = Monitor::all().unwrap().get(0) {
-                        println!("start recording.",);
-                        let monitor = monitor.clone();
-                        std::thread::spawn(|| {
-                            recorder::record(monitor);
-                        });
-                    } else {
-                        println!("no monitor");
-                    }
-
+                    // Set before spawning, a failed start resets it from the thread.
                     crate::RECORDING.store(true, Ordering::Release);
+                    let tray = tray.clone();
+                    std::thread::spawn(move || {
+                        if let Err(e) = recorder::record(source) {
+                            recording_failed(&tray, &e);
+                        }
+                    });
+
+                    create_tray(tray.app_handle()).unwrap();
                 }
             }
         }
@@ -171,3 +199,18 @@ fn set_recording_icon<R: Runtime>(tray: &tauri::tray::TrayIcon<R>, recording: bo
     let img = tauri::image::Image::from_path(path).unwrap();
     tray.set_icon(Some(img)).expect("set_icon failed");
 }
+
+/// Puts the tray back in its idle state and tells the user why recording stopped.
+fn recording_failed<R: Runtime>(tray: &tauri::tray::TrayIcon<R>, error: &recorder::RecorderError) {
+    crate::RECORDING.store(false, Ordering::Release);
+    set_recording_icon(tray, false);
+
+    let app = tray.app_handle();
+    app.notification()
+        .builder()
+        .title("Recording failed")
+        .body(error.to_string())
+        .show()
+        .unwrap_or_default();
+    create_tray(app).unwrap();
+}
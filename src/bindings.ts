@@ -16,6 +16,28 @@ async getWindowAlwaysOnTop() : Promise<boolean> {
 },
 async updateConfig(configContent: string) : Promise<void> {
     await TAURI_INVOKE("update_config", { configContent });
+},
+async listMonitors() : Promise<MonitorInfo[]> {
+    return await TAURI_INVOKE("list_monitors");
+},
+async listWindows() : Promise<WindowInfo[]> {
+    return await TAURI_INVOKE("list_windows");
+},
+async listAudioInputs() : Promise<AudioInputInfo[]> {
+    return await TAURI_INVOKE("list_audio_inputs");
+},
+/**
+ * Cameras that can be recorded, only V4L2 devices on Linux for now.
+ */
+async listCameras() : Promise<CameraInfo[]> {
+    return await TAURI_INVOKE("list_cameras");
+},
+/**
+ * Replaces the privacy masks in the recording under way, if any, and in the
+ * saved config.
+ */
+async setPrivacyMasks(masks: PrivacyMask[]) : Promise<void> {
+    await TAURI_INVOKE("set_privacy_masks", { masks });
 }
 }
 
@@ -24,10 +46,18 @@ async updateConfig(configContent: string) : Promise<void> {
 
 export const events = __makeEvents__<{
 pinnedFromTrayEvent: PinnedFromTrayEvent,
-pinnedFromWindowEvent: PinnedFromWindowEvent
+pinnedFromWindowEvent: PinnedFromWindowEvent,
+recordingFailed: RecordingFailed,
+recordingPaused: RecordingPaused,
+recordingStarted: RecordingStarted,
+recordingStopped: RecordingStopped
 }>({
 pinnedFromTrayEvent: "pinned-from-tray-event",
-pinnedFromWindowEvent: "pinned-from-window-event"
+pinnedFromWindowEvent: "pinned-from-window-event",
+recordingFailed: "recording-failed",
+recordingPaused: "recording-paused",
+recordingStarted: "recording-started",
+recordingStopped: "recording-stopped"
 })
 
 /** user-defined constants **/
@@ -36,8 +66,101 @@ pinnedFromWindowEvent: "pinned-from-window-event"
 
 /** user-defined types **/
 
+export type AudioInputConfig = { channels: number; minSampleRate: number; maxSampleRate: number; sampleFormat: string }
+export type AudioInputInfo = { name: string; isDefault: boolean; configs: AudioInputConfig[] }
+export type CameraFormat = { 
+/**
+ * `YUYV` or `MJPG`.
+ */
+fourcc: string; width: number; height: number }
+export type CameraInfo = { 
+/**
+ * Device node, what `camera_device` is set to.
+ */
+path: string; name: string; formats: CameraFormat[] }
+export type ConverterStats = { 
+/**
+ * Packets converted by this thread.
+ */
+frames: number; 
+/**
+ * Seconds spent converting, waiting on either queue excluded.
+ */
+busy: number }
+export type FrameStats = { 
+/**
+ * Frames captured and handed to the pipeline.
+ */
+captured: number; 
+/**
+ * Deadlines skipped because an earlier capture overran them.
+ */
+dropped: number; 
+/**
+ * Captures that finished after the next frame was already due.
+ */
+late: number; 
+/**
+ * Frames whose video was dropped by the overload policy.
+ */
+overloaded: number }
+export type MaskStyle = "pixelate" | "blur" | "fill"
+export type MonitorInfo = { id: number; name: string; width: number; height: number; isPrimary: boolean }
 export type PinnedFromTrayEvent = { pinned: boolean }
 export type PinnedFromWindowEvent = { pinned: boolean }
+/**
+ * An area kept out of recordings, fixed or following a window.
+ */
+export type PrivacyMask = { 
+/**
+ * Fixed area in the coordinates of the recorded monitor, or of the
+ * recorded window.
+ */
+region: Rect | null; 
+/**
+ * Title (or app name) of a window hidden wherever it moves. Used instead
+ * of `region` when set.
+ */
+window: string | null; 
+/**
+ * How the area is hidden, filled black when unset.
+ */
+style: MaskStyle | null; 
+/**
+ * Block size for `pixelate` or radius for `blur`, in captured pixels.
+ */
+strength: number | null }
+export type RecordingFailed = { reason: string }
+/**
+ * Emitted on pause and on resume, `paused` tells which.
+ */
+export type RecordingPaused = { paused: boolean }
+export type RecordingStarted = { path: string }
+export type RecordingStopped = { path: string; 
+/**
+ * Recorded duration in seconds, paused time excluded.
+ */
+duration: number; bytes: number; frames: FrameStats; converters: ConverterStats[]; reorder: ReorderStats }
+export type Rect = { x: number; y: number; width: number; height: number }
+export type ReorderStats = { 
+/**
+ * Packets that arrived ahead of an earlier one and had to wait.
+ */
+reordered: number; 
+/**
+ * Most packets waiting at once.
+ */
+maxPending: number; 
+/**
+ * Sequence numbers given up on after the gap timeout.
+ */
+skipped: number; 
+/**
+ * Packets that arrived after their slot was skipped. Their video is
+ * dropped, their audio goes out with the next packet.
+ */
+late: number }
+export type WindowInfo = { id: number; appName: string; title: string }
 
 /** tauri-specta globals **/
 